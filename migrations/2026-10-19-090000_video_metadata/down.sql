@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+alter table videos
+  drop column channel_id,
+  drop column channel_title,
+  drop column published_at,
+  drop column category_id,
+  drop column thumbnail_default_url,
+  drop column thumbnail_medium_url,
+  drop column thumbnail_high_url,
+  drop column thumbnail_maxres_url,
+  drop column duration_seconds,
+  drop column is_live_broadcast;
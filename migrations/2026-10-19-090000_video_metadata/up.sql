@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+alter table videos
+  add column channel_id text,
+  add column channel_title text,
+  add column published_at timestamp,
+  add column category_id text,
+  add column thumbnail_default_url text,
+  add column thumbnail_medium_url text,
+  add column thumbnail_high_url text,
+  add column thumbnail_maxres_url text,
+  add column duration_seconds bigint,
+  add column is_live_broadcast boolean not null default false;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- The original, viewer-specific urls and the folded duplicates are not recoverable so only the indexes are dropped
+
+drop index uservideos_user_id_video_id_key;
+drop index if exists videos_youtube_video_id_key;
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+-- A user who watched several of the duplicates keeps only their first watch, so folding
+-- the duplicates below cannot give them the same video twice
+delete from uservideos later
+  using videos later_video, uservideos earlier, videos earlier_video
+  where later.video_id = later_video.id
+    and earlier.video_id = earlier_video.id
+    and earlier.user_id = later.user_id
+    and earlier_video.youtube_video_id = later_video.youtube_video_id
+    and earlier.id < later.id;
+
+-- Fold duplicate rows for the same youtube video into the oldest one
+update uservideos
+  set video_id = keep.id
+  from videos duplicate, (select min(id) as id, youtube_video_id from videos group by youtube_video_id) keep
+  where uservideos.video_id = duplicate.id
+    and duplicate.youtube_video_id = keep.youtube_video_id
+    and duplicate.id <> keep.id;
+
+delete from videos duplicate
+  using videos keep
+  where duplicate.youtube_video_id = keep.youtube_video_id
+    and duplicate.id > keep.id;
+
+update videos
+  set video_url = 'https://www.youtube.com/watch?v=' || youtube_video_id;
+
+create unique index videos_youtube_video_id_key on videos (youtube_video_id);
+create unique index uservideos_user_id_video_id_key on uservideos (user_id, video_id);
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+-- Videos from the other platforms stay, with their ids left in youtube_video_id. Their ids can
+-- collide with each other, so youtube_video_id is not made unique again.
+
+drop index videos_platform_external_id_key;
+
+alter table videos drop column platform;
+alter table videos rename column external_id to youtube_video_id;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+drop index videos_youtube_video_id_key;
+
+alter table videos rename column youtube_video_id to external_id;
+alter table videos add column platform text not null default 'youtube';
+
+create unique index videos_platform_external_id_key on videos (platform, external_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE messages;
+
+alter table userfriends drop column is_friend_blocked;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+alter table userfriends add column is_friend_blocked boolean not null default false;
+
+create table messages (
+  id bigserial primary key not null,
+  sender_google_uid text not null REFERENCES usermaster(uid),
+  recipient_google_uid text not null REFERENCES usermaster(uid),
+  body text not null,
+  delivered_at timestamp,
+  read_at timestamp,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create index messages_recipient_google_uid_idx on messages (recipient_google_uid, sender_google_uid, id);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+create table videoreactions (
+  id bigserial primary key not null,
+  video_id BIGINT not null REFERENCES videos(id),
+  watcher_google_uid text not null REFERENCES usermaster(uid),
+  reactor_google_uid text not null REFERENCES usermaster(uid),
+  emoji text,
+  comment text,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create index videoreactions_watcher_google_uid_idx on videoreactions (watcher_google_uid, video_id);
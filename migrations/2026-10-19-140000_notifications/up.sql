@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+create table notifications (
+  id bigserial primary key not null,
+  recipient_google_uid text not null REFERENCES usermaster(uid),
+  action text not null,
+  payload text not null,
+  delivered_at timestamp,
+  read_at timestamp,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create index notifications_unread_idx on notifications (recipient_google_uid, id) where read_at is null;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+create table videoshares (
+  id bigserial primary key not null,
+  video_id BIGINT not null REFERENCES videos(id),
+  sender_google_uid text not null REFERENCES usermaster(uid),
+  recipient_google_uid text not null REFERENCES usermaster(uid),
+  note text,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create index videoshares_recipient_google_uid_idx on videoshares (recipient_google_uid, created_at);
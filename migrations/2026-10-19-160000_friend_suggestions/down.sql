@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX uservideos_video_id_idx;
+DROP INDEX uservideos_user_id_idx;
+DROP INDEX userfriends_friend_google_uid_idx;
+DROP INDEX userfriends_user_google_uid_idx;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+create index userfriends_user_google_uid_idx on userfriends (user_google_uid, friend_google_uid);
+create index userfriends_friend_google_uid_idx on userfriends (friend_google_uid, user_google_uid);
+create index uservideos_user_id_idx on uservideos (user_id, created_at);
+create index uservideos_video_id_idx on uservideos (video_id, user_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX usermaster_search_name_trgm_idx;
+ALTER TABLE usermaster DROP COLUMN is_discoverable;
+DROP FUNCTION search_name(text);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+create extension if not exists unaccent;
+create extension if not exists pg_trgm;
+
+-- unaccent() is only STABLE, which expression indexes refuse
+create or replace function search_name(name text) returns text as $$
+  select lower(public.unaccent('public.unaccent'::regdictionary, name))
+$$ language sql immutable parallel safe strict;
+
+alter table usermaster add column is_discoverable boolean not null default true;
+
+create index usermaster_search_name_trgm_idx on usermaster using gin (search_name(full_name) gin_trgm_ops);
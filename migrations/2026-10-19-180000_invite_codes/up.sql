@@ -0,0 +1,22 @@
+-- Your SQL goes here
+
+create table invitecodes (
+  id bigserial primary key not null,
+  code text not null unique,
+  inviter_google_uid text not null REFERENCES usermaster(uid),
+  max_uses integer,
+  use_count integer not null default 0,
+  expires_at timestamp,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create index invitecodes_inviter_google_uid_idx on invitecodes (inviter_google_uid);
+
+create table inviteredemptions (
+  id bigserial primary key not null,
+  invite_code_id BIGINT not null REFERENCES invitecodes(id),
+  invitee_google_uid text not null unique REFERENCES usermaster(uid),
+  created_at timestamp not null,
+  updated_at timestamp
+);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+-- Rebuilt wholesale by the server, see recommendations.rs
+create table videosimilarities (
+  video_id BIGINT not null REFERENCES videos(id),
+  similar_video_id BIGINT not null REFERENCES videos(id),
+  co_watch_count BIGINT not null,
+  score double precision not null,
+  created_at timestamp not null,
+  primary key (video_id, similar_video_id)
+);
+
+create index videosimilarities_video_id_score_idx on videosimilarities (video_id, score desc);
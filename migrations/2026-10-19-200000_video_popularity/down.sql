@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX videos_view_count_idx;
+ALTER TABLE videos DROP COLUMN unique_viewer_count;
+ALTER TABLE videos DROP COLUMN view_count;
+DROP TABLE videoviews;
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+-- One row per watch, uservideos only keeps the first one per user
+create table videoviews (
+  id bigserial primary key not null,
+  video_id BIGINT not null REFERENCES videos(id),
+  user_id BIGINT not null REFERENCES usermaster(id),
+  created_at timestamp not null
+);
+
+create index videoviews_created_at_idx on videoviews (created_at, video_id);
+create index videoviews_video_id_idx on videoviews (video_id);
+
+insert into videoviews (video_id, user_id, created_at)
+select video_id, user_id, created_at from uservideos;
+
+alter table videos add column view_count BIGINT not null default 0;
+alter table videos add column unique_viewer_count BIGINT not null default 0;
+
+update videos set
+  view_count = counts.view_count,
+  unique_viewer_count = counts.unique_viewer_count
+from (
+  select video_id, count(*) as view_count, count(distinct user_id) as unique_viewer_count
+  from videoviews
+  group by video_id
+) counts
+where counts.video_id = videos.id;
+
+create index videos_view_count_idx on videos (view_count desc, unique_viewer_count desc);
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+create table playlists (
+  id bigserial primary key not null,
+  platform text not null,
+  external_id text not null,
+  title text not null,
+  channel_id text,
+  channel_title text,
+  item_count BIGINT,
+  thumbnail_url text,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create unique index playlists_platform_external_id_idx on playlists (platform, external_id);
+
+create table playlistvideos (
+  id bigserial primary key not null,
+  playlist_id BIGINT not null REFERENCES playlists(id),
+  video_id BIGINT not null REFERENCES videos(id),
+  position integer,
+  created_at timestamp not null,
+  updated_at timestamp
+);
+
+create unique index playlistvideos_playlist_id_video_id_idx on playlistvideos (playlist_id, video_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE usermaster DROP COLUMN last_video_id;
+ALTER TABLE usermaster DROP COLUMN last_seen_at;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+alter table usermaster
+  add column last_seen_at timestamp,
+  add column last_video_id BIGINT REFERENCES videos(id);
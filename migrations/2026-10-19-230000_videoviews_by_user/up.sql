@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- The personal stats read one user's views at a time
+create index videoviews_user_id_idx on videoviews (user_id, created_at);
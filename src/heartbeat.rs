@@ -0,0 +1,67 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+use ws::util::Token;
+
+
+pub const PING: Token = Token(1);
+pub const EXPIRE: Token = Token(2);
+
+const DEFAULT_PING_INTERVAL_SECS: u64 = 30;
+const DEFAULT_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_AWAY_AFTER_SECS: u64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatSettings {
+    pub ping_interval: Duration,
+    // A socket that sends neither a pong nor a message for this long is evicted
+    pub timeout: Duration,
+    pub away_after: Duration,
+}
+
+impl HeartbeatSettings {
+    // HEARTBEAT_INTERVAL_SECS, HEARTBEAT_TIMEOUT_SECS and AWAY_AFTER_SECS override the defaults
+    pub fn from_env() -> HeartbeatSettings {
+        let secs_from_env = |name: &str, default_secs: u64| {
+            env::var(name).ok()
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .unwrap_or(default_secs)
+        };
+
+        let ping_interval = Duration::from_secs(secs_from_env("HEARTBEAT_INTERVAL_SECS", DEFAULT_PING_INTERVAL_SECS));
+        let timeout = Duration::from_secs(secs_from_env("HEARTBEAT_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS));
+        let away_after = Duration::from_secs(secs_from_env("AWAY_AFTER_SECS", DEFAULT_AWAY_AFTER_SECS));
+
+        // A timeout shorter than two intervals would evict healthy sockets whose pong is a little late
+        let timeout = if timeout < ping_interval * 2 {
+            println!("HEARTBEAT_TIMEOUT_SECS is below twice the ping interval, using {:?}", ping_interval * 2);
+            ping_interval * 2
+        } else {
+            timeout
+        };
+
+        HeartbeatSettings { ping_interval, timeout, away_after }
+    }
+}
+
+// Someone playing a video sends nothing for as long as it runs, that is not being away
+pub fn is_idle(last_activity: Instant, now: Instant, is_playing: bool, away_after: Duration) -> bool {
+    !is_playing && now.saturating_duration_since(last_activity) >= away_after
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_only_when_inactive_and_not_playing() {
+        let start = Instant::now();
+        let away_after = Duration::from_secs(600);
+
+        assert!(!is_idle(start, start + Duration::from_secs(599), false, away_after));
+        assert!(is_idle(start, start + Duration::from_secs(600), false, away_after));
+        assert!(!is_idle(start, start + Duration::from_secs(3600), true, away_after));
+    }
+}
@@ -0,0 +1,373 @@
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value as JsonValue};
+use tubepeek_server_rust::repositories::MemoryRepository;
+use ws::{CloseCode, Handler, Handshake, Message, Sender};
+
+use crate::heartbeat::HeartbeatSettings;
+use crate::metadata::{MetadataProvider, PlaylistMetadata, VideoMetadata, VideoThumbnails};
+use crate::platform::{Platform, VideoUrl};
+use crate::{ServerContext, StorageBackend, WsServer};
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+const VIDEO_URL: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+
+// Every video is known and titled after its id, playlists are never described
+struct FixtureMetadata;
+
+impl MetadataProvider for FixtureMetadata {
+    fn video_metadata(&self, video_url: &VideoUrl) -> Result<VideoMetadata, &'static str> {
+        Ok(VideoMetadata {
+            title: format!("Fixture {}", video_url.external_id),
+            channel_id: "fixture-channel".to_string(),
+            channel_title: "Fixture channel".to_string(),
+            published_at: None,
+            category_id: None,
+            thumbnails: VideoThumbnails {
+                default: format!("https://img.example/{}.jpg", video_url.external_id),
+                medium: None,
+                high: None,
+                maxres: None,
+            },
+            duration_seconds: Some(213),
+            is_live_broadcast: false,
+        })
+    }
+
+    fn playlist_metadata(&self, _platform: Platform, _playlist_id: &str) -> Result<PlaylistMetadata, &'static str> {
+        Err("No playlist fixtures")
+    }
+
+    fn playlist_position(&self, _platform: Platform, _playlist_id: &str, _external_id: &str) -> Result<u32, &'static str> {
+        Err("No playlist fixtures")
+    }
+}
+
+// The real server on an ephemeral port, backed by the in-memory storage. Shuts down when dropped.
+struct TestServer {
+    url: String,
+    broadcaster: Sender,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        let context = ServerContext::new(
+            StorageBackend::Memory(Arc::new(MemoryRepository::new())),
+            Arc::new(FixtureMetadata),
+            HeartbeatSettings::from_env()
+        );
+
+        let web_socket = ws::WebSocket::new(move |out| WsServer::new(out, &context))
+            .expect("Failed to create the test server")
+            .bind("127.0.0.1:0")
+            .expect("Failed to bind the test server");
+        let url = format!("ws://{}", web_socket.local_addr().unwrap());
+        let broadcaster = web_socket.broadcaster();
+
+        let thread = thread::spawn(move || {
+            web_socket.run().expect("The test server stopped with an error");
+        });
+
+        TestServer { url, broadcaster, thread: Some(thread) }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.broadcaster.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct ClientHandler {
+    out: Sender,
+    opened: Option<mpsc::Sender<Sender>>,
+    received: mpsc::Sender<JsonValue>,
+}
+
+impl Handler for ClientHandler {
+    fn on_open(&mut self, _shake: Handshake) -> ws::Result<()> {
+        if let Some(opened) = self.opened.take() {
+            let _ = opened.send(self.out.clone());
+        }
+        Ok(())
+    }
+
+    // Anything that is not json, like "Unknown message type", fails the expectation it was meant for
+    fn on_message(&mut self, msg: Message) -> ws::Result<()> {
+        let message = serde_json::from_str(&msg.into_text()?).unwrap_or(JsonValue::Null);
+        let _ = self.received.send(message);
+        Ok(())
+    }
+}
+
+// A browser extension signed in as one user, on its own socket
+struct FakeUser {
+    google_user_id: String,
+    socket: Sender,
+    received: mpsc::Receiver<JsonValue>,
+    thread: JoinHandle<()>,
+}
+
+impl FakeUser {
+    fn connect(server: &TestServer, google_user_id: &str) -> FakeUser {
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let (received_tx, received_rx) = mpsc::channel();
+        let url = server.url.clone();
+
+        let thread = thread::spawn(move || {
+            ws::connect(url, |out| ClientHandler {
+                out,
+                opened: Some(opened_tx.clone()),
+                received: received_tx.clone(),
+            }).expect("The fake user's socket failed");
+        });
+
+        let socket = opened_rx.recv_timeout(RECEIVE_TIMEOUT).expect("The fake user could not connect");
+        FakeUser { google_user_id: google_user_id.to_string(), socket, received: received_rx, thread }
+    }
+
+    fn send(&self, message: JsonValue) {
+        self.socket.send(message.to_string()).unwrap();
+    }
+
+    // Skips everything else that arrives in the meantime, such as the "{}" acknowledgements
+    fn expect(&self, action: &str) -> JsonValue {
+        self.expect_skipping(action).0
+    }
+
+    // Also hands back what was skipped, for checking something did not arrive
+    fn expect_skipping(&self, action: &str) -> (JsonValue, Vec<JsonValue>) {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        let mut skipped = vec![];
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.received.recv_timeout(remaining) {
+                Ok(message) if message["action"] == action => return (message, skipped),
+                Ok(message) => skipped.push(message),
+                Err(_) => break,
+            }
+        }
+        panic!("{} never got {}, only {:?}", self.google_user_id, action, skipped);
+    }
+
+    // Waits out the whole timeout, for checking something never arrives
+    fn expect_no(&self, action: &str) {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.received.recv_timeout(remaining) {
+                Ok(message) => assert_ne!(message["action"], action, "{} got {:?}", self.google_user_id, message),
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn identify(&self) -> JsonValue {
+        self.send_identity();
+        self.expect("TakeVideosBeingWatched")
+    }
+
+    fn send_identity(&self) {
+        self.send(json!({
+            "action": "TakeUserMessage",
+            "provider": "google",
+            "authData": {
+                "uid": self.google_user_id,
+                "fullName": format!("Fake {}", self.google_user_id),
+                "imageUrl": format!("https://img.example/{}.png", self.google_user_id)
+            }
+        }));
+    }
+
+    fn disconnect(self) {
+        self.socket.close(CloseCode::Normal).unwrap();
+        self.thread.join().unwrap();
+    }
+}
+
+fn friend_entry<'a>(take_videos_being_watched: &'a JsonValue, friend_google_user_id: &str) -> &'a JsonValue {
+    take_videos_being_watched["friendsOnTubePeek"].as_array().unwrap().iter()
+        .find(|friend| friend["friend_google_uid"] == friend_google_user_id)
+        .unwrap_or_else(|| panic!("{} is not among the friends", friend_google_user_id))
+}
+
+// Online friends are picked up when identifying, so new friends identify again to see each other
+fn befriend(alice: &FakeUser, bob: &FakeUser) {
+    alice.send(json!({"action": "MakeFriendship", "googleUserId": alice.google_user_id, "friendGoogleUserId": bob.google_user_id}));
+
+    let new_friend = bob.expect("NewFriendOnTubePeek");
+    assert_eq!(new_friend["friendDetails"]["googleUserId"], alice.google_user_id.as_str());
+    let new_friend = alice.expect("NewFriendOnTubePeek");
+    assert_eq!(new_friend["friendDetails"]["googleUserId"], bob.google_user_id.as_str());
+
+    alice.identify();
+    bob.identify();
+}
+
+
+#[test]
+fn friends_see_each_others_videos_and_departures() {
+    let server = TestServer::start();
+    let alice = FakeUser::connect(&server, "alice");
+    let bob = FakeUser::connect(&server, "bob");
+
+    let welcome = alice.identify();
+    assert_eq!(welcome["friendsOnTubePeek"], json!([]));
+    bob.identify();
+
+    befriend(&alice, &bob);
+
+    bob.send(json!({"action": "ChangedVideo", "videoUrl": VIDEO_URL}));
+    let video_change = alice.expect("TakeFriendVideoChange");
+    assert_eq!(video_change["googleUserId"], "bob");
+    assert_eq!(video_change["videoData"]["title"], "Fixture dQw4w9WgXcQ");
+    assert_eq!(video_change["friendData"]["full_name"], "Fake bob");
+
+    bob.disconnect();
+    let online_status = alice.expect("TakeFriendOnlineStatus");
+    assert_eq!((&online_status["googleUserId"], &online_status["onlineState"]), (&json!("bob"), &json!(false)));
+
+    let welcome = alice.identify();
+    let bob_entry = friend_entry(&welcome, "bob");
+    assert!(bob_entry["last_seen_at_in_milliseconds"].is_i64());
+    assert_eq!(bob_entry["last_video"]["title"], "Fixture dQw4w9WgXcQ");
+}
+
+#[test]
+fn excluded_friends_are_kept_out_of_sight() {
+    let server = TestServer::start();
+    let alice = FakeUser::connect(&server, "alice");
+    let bob = FakeUser::connect(&server, "bob");
+    alice.identify();
+    bob.identify();
+    befriend(&alice, &bob);
+
+    bob.send(json!({"action": "ChangedVideo", "videoUrl": VIDEO_URL}));
+    alice.expect("TakeFriendVideoChange");
+
+    let exclusion = |exclude: bool| {
+        alice.send(json!({"action": "FriendExclusion", "friendGoogleUserId": "bob", "exclude": exclude}));
+        alice.identify()
+    };
+
+    exclusion(true);
+    alice.send(json!({"action": "ChangedVideo", "videoUrl": VIDEO_URL}));
+    bob.expect_no("TakeFriendVideoChange");
+
+    bob.send(json!({"action": "GetFriendsActivity"}));
+    let activity = bob.expect("TakeFriendsActivity");
+    assert_eq!((&activity["onlineFriends"], &activity["friendsOnYoutubeNow"]), (&json!([]), &json!([])));
+
+    bob.disconnect();
+    let welcome = alice.identify();
+    let bob_entry = friend_entry(&welcome, "bob");
+    assert_eq!(bob_entry["is_friend_excluded"], true);
+    assert!(bob_entry["last_seen_at_in_milliseconds"].is_null());
+    assert!(bob_entry["last_video"].is_null());
+
+    let welcome = exclusion(false);
+    let bob_entry = friend_entry(&welcome, "bob");
+    assert_eq!(bob_entry["is_friend_excluded"], false);
+    assert!(bob_entry["last_seen_at_in_milliseconds"].is_i64());
+    assert_eq!(bob_entry["last_video"]["title"], "Fixture dQw4w9WgXcQ");
+}
+
+#[test]
+fn blocked_friends_lose_sight_of_the_blocker() {
+    let server = TestServer::start();
+    let alice = FakeUser::connect(&server, "alice");
+    let bob = FakeUser::connect(&server, "bob");
+    alice.identify();
+    bob.identify();
+    befriend(&alice, &bob);
+
+    alice.send(json!({"action": "FriendBlock", "friendGoogleUserId": "bob", "block": true}));
+    alice.send(json!({"action": "ChangedVideo", "videoUrl": VIDEO_URL}));
+    bob.expect_no("TakeFriendVideoChange");
+
+    bob.send(json!({"action": "GetFriendsActivity"}));
+    let activity = bob.expect("TakeFriendsActivity");
+    assert_eq!((&activity["onlineFriends"], &activity["friendsOnYoutubeNow"]), (&json!([]), &json!([])));
+
+    bob.send(json!({"action": "JoinFriendVideo", "friendGoogleUserId": "alice"}));
+    assert_eq!(bob.expect("ERROR")["message"], "Not friends");
+}
+
+#[test]
+fn users_stay_online_until_their_last_tab_closes() {
+    let server = TestServer::start();
+    let alice = FakeUser::connect(&server, "alice");
+    let bob = FakeUser::connect(&server, "bob");
+    alice.identify();
+    bob.identify();
+    befriend(&alice, &bob);
+
+    let alice_second_tab = FakeUser::connect(&server, "alice");
+    alice_second_tab.identify();
+    bob.identify();
+
+    bob.send(json!({"action": "ChangedVideo", "videoUrl": VIDEO_URL}));
+    alice.expect("TakeFriendVideoChange");
+    alice_second_tab.expect("TakeFriendVideoChange");
+
+    // The server is done with a closed socket before the closing handshake completes
+    alice.disconnect();
+    bob.send_identity();
+    let (welcome, skipped) = bob.expect_skipping("TakeVideosBeingWatched");
+    assert!(skipped.iter().all(|message| message["action"] != "TakeFriendOnlineStatus"), "{:?}", skipped);
+    assert!(friend_entry(&welcome, "alice")["last_seen_at_in_milliseconds"].is_null());
+
+    alice_second_tab.disconnect();
+    let online_status = bob.expect("TakeFriendOnlineStatus");
+    assert_eq!((&online_status["googleUserId"], &online_status["onlineState"]), (&json!("alice"), &json!(false)));
+}
+
+#[test]
+fn chat_messages_reach_friends_online_or_not() {
+    let server = TestServer::start();
+    let alice = FakeUser::connect(&server, "alice");
+    let bob = FakeUser::connect(&server, "bob");
+    alice.identify();
+    bob.identify();
+    befriend(&alice, &bob);
+
+    alice.send(json!({"action": "SendChatMessage", "friendGoogleUserId": "bob", "body": " Hi bob ", "clientMessageId": "first"}));
+    let received = bob.expect("TakeChatMessage");
+    assert_eq!(received["message"]["senderGoogleUserId"], "alice");
+    assert_eq!(received["message"]["body"], "Hi bob");
+    let sent = alice.expect("TakeChatMessageSent");
+    assert_eq!(sent["clientMessageId"], "first");
+    assert_eq!(sent["message"]["messageId"], received["message"]["messageId"]);
+    assert!(sent["message"]["deliveredAtInMilliseconds"].is_i64());
+
+    bob.send(json!({"action": "MarkChatMessagesRead", "friendGoogleUserId": "alice", "upToMessageId": received["message"]["messageId"]}));
+    let receipt = alice.expect("TakeChatMessageReceipt");
+    assert_eq!((&receipt["status"], &receipt["friendGoogleUserId"]), (&json!("read"), &json!("bob")));
+    assert_eq!(receipt["messageIds"], json!([received["message"]["messageId"]]));
+
+    bob.disconnect();
+    alice.expect("TakeFriendOnlineStatus");
+    alice.send(json!({"action": "SendChatMessage", "friendGoogleUserId": "bob", "body": "Still there?", "clientMessageId": "second"}));
+    let sent = alice.expect("TakeChatMessageSent");
+    assert!(sent["message"]["deliveredAtInMilliseconds"].is_null());
+
+    let bob = FakeUser::connect(&server, "bob");
+    let welcome = bob.identify();
+    let waiting_messages: Vec<&JsonValue> = welcome["notifications"].as_array().unwrap().iter()
+        .map(|notification| &notification["event"])
+        .filter(|event| event["action"] == "TakeChatMessage")
+        .collect();
+    assert_eq!(waiting_messages.len(), 1);
+    assert_eq!(waiting_messages[0]["message"]["body"], "Still there?");
+
+    let receipt = alice.expect("TakeChatMessageReceipt");
+    assert_eq!((&receipt["status"], &receipt["friendGoogleUserId"]), (&json!("delivered"), &json!("bob")));
+    assert_eq!(receipt["messageIds"], json!([sent["message"]["messageId"]]));
+}
@@ -2,5 +2,9 @@
 extern crate diesel;
 extern crate chrono;
 
+// diesel's table! and derive macros expand to impls inside consts
+#[allow(non_local_definitions)]
 pub mod schema;
+#[allow(non_local_definitions)]
 pub mod models;
+pub mod repositories;
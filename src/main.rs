@@ -2,55 +2,188 @@
 extern crate lazy_static;
 
 mod db_pool;
-use db_pool::{establish_connection, PgPool};
+use db_pool::{establish_connection, PgPool, PgPooledConnection};
+use diesel::r2d2::PoolError;
 
+// Kept for the dotenv().ok() call in main, which is commented out for now
+#[allow(unused_imports)]
 use dotenv::dotenv;
 use std::env;
 
+// Field names follow the json the extension sends and expects
+#[allow(non_snake_case)]
 mod ws_dto;
 use ws_dto::*;
 
 mod utils;
-use utils::*;
+use utils::to_milliseconds;
+
+mod platform;
+use platform::{parse_video_url, VideoUrl};
+
+mod metadata;
+use metadata::{MetadataProvider, PlatformMetadata, PlaylistMetadata, VideoMetadata, VideoThumbnails};
+
+mod watch_party;
+use watch_party::{WatchParties, WatchPartyDeparture, WatchPartyMember};
+
+mod ttl_cache;
+use ttl_cache::TtlCache;
+
+mod recommendations;
+use recommendations::{load_recommendations, spawn_similarity_rebuilds};
+
+mod stats;
+use stats::{load_user_stats, youtube_category_name};
+
+mod heartbeat;
+use heartbeat::{is_idle, HeartbeatSettings};
+
+mod registry;
+use registry::{ConnectionRegistry, Connections};
+
+#[cfg(test)]
+mod integration_tests;
 
 use ws::{Result as WsResult};
-use ws::{listen, CloseCode, Handler, Message, Sender};
+use ws::{listen, CloseCode, Frame, Handler, Handshake, Message, OpCode, Sender};
+use ws::util::{Timeout, Token};
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use diesel::prelude::*;
-use diesel::PgConnection;
 use serde_json::{json, Error, Value as JsonValue};
 
-use chrono::{NaiveDateTime, Utc};
-use tubepeek_server_rust::models::{NewUser, NewUserFriend, Usermaster, Video, NewVideo, UserVideo, NewUserVideo, UserFriend, UserFriendEntity};
-
+use chrono::{TimeZone, Utc};
+use rand::seq::SliceRandom;
+use tubepeek_server_rust::repositories::{DiscoveryRepository, FriendshipRepository, InboxRepository, MemoryRepository, PgRepository, Storage};
+use tubepeek_server_rust::models::{NewUser, NewUserFriend, Usermaster, Video, NewVideo, VideoMetadataUpdate, UserFriend, UserFriendEntity, Message as ChatMessage, NewMessage, VideoReaction, NewVideoReaction, NewNotification, NewVideoShare, PublicProfile, NewInviteCode, Playlist, NewPlaylist, PlaylistMetadataUpdate, NewPlaylistVideo};
+
+
+const MAX_CHAT_MESSAGE_LENGTH: usize = 2000;
+const MAX_REACTION_EMOJI_LENGTH: usize = 16;
+const MAX_REACTION_COMMENT_LENGTH: usize = 280;
+const DEFAULT_WATCH_HISTORY_PAGE_SIZE: i64 = 50;
+const MAX_PENDING_NOTIFICATIONS: i64 = 100;
+const MAX_SHARE_NOTE_LENGTH: usize = 280;
+const DEFAULT_FRIEND_SUGGESTIONS_LIMIT: i64 = 20;
+// Only the most recent watches count towards video overlap, which keeps the join bounded for heavy watchers
+const FRIEND_SUGGESTIONS_RECENT_VIDEOS: i64 = 200;
+const DEFAULT_USER_SEARCH_LIMIT: i64 = 20;
+const MAX_USER_SEARCH_QUERY_LENGTH: usize = 100;
+const INVITE_CODE_LENGTH: usize = 10;
+// No 0/O or 1/I/L, codes get read aloud and typed in by hand
+const INVITE_CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+const DEFAULT_INVITE_CODE_TTL_DAYS: i64 = 30;
+const MAX_INVITE_CODE_TTL_DAYS: i64 = 365;
+const DEFAULT_FRIENDS_TRENDING_LIMIT: i64 = 20;
+const FRIENDS_TRENDING_CACHE_CAPACITY: usize = 10_000;
+const DEFAULT_RECOMMENDATIONS_PAGE_SIZE: i64 = 20;
+const DEFAULT_LEADERBOARD_PAGE_SIZE: i64 = 50;
+// Playlist titles and sizes are refetched at most this often while people watch through them
+const PLAYLIST_METADATA_TTL_MINUTES: i64 = 60;
 
 // Using lazy static to have a global reference to my connection pool
 // However, I feel that for testing/mocking this won't be great.
 lazy_static! {
-    static ref POOL: PgPool = { establish_connection() };
-    static ref WS_CONNECTED_CLIENTS: Mutex<HashMap<u32, WsConnectedClientMetadata>> =
-        Mutex::new(HashMap::new());
+    static ref POOL: PgPool = establish_connection();
 }
 
+type FriendsTrendingCache = Mutex<TtlCache<(String, TrendingWindow, i64), String>>;
+
 #[derive(Debug)]
+#[allow(non_snake_case)]
 pub struct WsConnectedClientMetadata {
     pub socketId: u32,
     pub socket: Sender,
     pub googleUserId: String,
     pub currentVideo: Option<WsConnectedClientCurrentVideo>,
     pub onlineFriends: Box<Vec<WsOnlineFriend>>,
+    pub isAway: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
 pub struct WsConnectedClientCurrentVideo {
+    pub platform: String,
+    pub externalId: String,
     pub videoUrl: String,
+    pub startSeconds: Option<i64>,
+    pub playlistId: Option<String>,
+    pub playlistIndex: Option<u32>,
     pub title: String,
     pub thumbnail_url: String,
-    pub timeStampInMilliseconds: i64
+    pub thumbnails: VideoThumbnails,
+    pub channelId: String,
+    pub channelTitle: String,
+    pub publishedAt: Option<String>,
+    pub categoryId: Option<String>,
+    pub durationInSeconds: Option<i64>,
+    pub isLiveBroadcast: bool,
+    pub timeStampInMilliseconds: i64,
+    pub playbackState: PlaybackState,
+    pub positionInSeconds: f64,
+    pub positionTimeStampInMilliseconds: i64,
+    pub playlist: Option<WsCurrentPlaylist>
+}
+
+// Title and size are missing for playlists the platform will not describe, such as YouTube mixes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct WsCurrentPlaylist {
+    pub playlistId: String,
+    pub title: Option<String>,
+    pub channelTitle: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub itemCount: Option<i64>,
+    // One based
+    pub position: Option<u32>
+}
+
+impl WsConnectedClientCurrentVideo {
+    pub fn from(video_url: &VideoUrl, video_metadata: &VideoMetadata, time_stamp_in_milliseconds: i64) -> WsConnectedClientCurrentVideo {
+        WsConnectedClientCurrentVideo {
+            platform: video_url.platform.to_string(),
+            externalId: video_url.external_id.to_string(),
+            videoUrl: video_url.canonical_url(),
+            startSeconds: video_url.start_seconds,
+            playlistId: video_url.playlist_id.clone(),
+            playlistIndex: video_url.playlist_index,
+            title: video_metadata.title.to_string(),
+            thumbnail_url: video_metadata.thumbnails.default.to_string(),
+            thumbnails: video_metadata.thumbnails.clone(),
+            channelId: video_metadata.channel_id.to_string(),
+            channelTitle: video_metadata.channel_title.to_string(),
+            publishedAt: video_metadata.published_at
+                .map(|published_at| Utc.from_utc_datetime(&published_at).to_rfc3339()),
+            categoryId: video_metadata.category_id.clone(),
+            durationInSeconds: video_metadata.duration_seconds,
+            isLiveBroadcast: video_metadata.is_live_broadcast,
+            timeStampInMilliseconds: time_stamp_in_milliseconds,
+            playbackState: PlaybackState::Playing,
+            positionInSeconds: video_url.start_seconds.unwrap_or(0) as f64,
+            positionTimeStampInMilliseconds: time_stamp_in_milliseconds,
+            playlist: None
+        }
+    }
+
+    // The last reported position plus however long the video has been playing since
+    pub fn estimated_position(&self, now_in_milliseconds: i64) -> f64 {
+        let position_in_seconds = match self.playbackState {
+            PlaybackState::Playing => {
+                let elapsed_milliseconds = (now_in_milliseconds - self.positionTimeStampInMilliseconds).max(0);
+                self.positionInSeconds + elapsed_milliseconds as f64 / 1000.0
+            },
+            _ => self.positionInSeconds
+        };
+
+        match self.durationInSeconds {
+            Some(duration_in_seconds) if duration_in_seconds > 0 => position_in_seconds.min(duration_in_seconds as f64),
+            _ => position_in_seconds
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,24 +193,82 @@ pub struct CurrentVideoFriend {
 }
 
 #[derive(Debug)]
+#[allow(non_snake_case)]
 pub struct WsOnlineFriend {
     pub socketId: u32,
     pub googleUserId: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
 pub struct WsFriendCurrentVideo {
     pub googleUserId: String,
     pub videoData: WsConnectedClientCurrentVideo,
     pub friendData: CurrentVideoFriend
 }
 
+// Where the handlers persist to. The in-memory storage is for tests and for running the server
+// without a database, everything in it is gone once the server stops.
+#[derive(Clone)]
+enum StorageBackend {
+    Postgres,
+    Memory(Arc<MemoryRepository>),
+}
+
+impl StorageBackend {
+    fn with_storage<T>(&self, action: impl FnOnce(&Storage) -> T) -> Result<T, PoolError> {
+        match self {
+            StorageBackend::Postgres => {
+                let db_conn: PgPooledConnection = POOL.get()?;
+                let pg_repository = PgRepository::new(&db_conn);
+                Ok(action(&Storage::postgres(&pg_repository)))
+            },
+            StorageBackend::Memory(memory_repository) => Ok(action(&Storage::memory(memory_repository))),
+        }
+    }
+}
+
+// Shared by the handlers of every socket
+#[derive(Clone)]
+struct ServerContext {
+    registry: Arc<ConnectionRegistry>,
+    storage: StorageBackend,
+    metadata: Arc<dyn MetadataProvider>,
+    // Always locked after the ConnectionRegistry, never before it
+    watch_parties: Arc<Mutex<WatchParties>>,
+    friends_trending_cache: Arc<FriendsTrendingCache>,
+    heartbeat: HeartbeatSettings,
+}
+
+impl ServerContext {
+    fn new(storage: StorageBackend, metadata: Arc<dyn MetadataProvider>, heartbeat: HeartbeatSettings) -> ServerContext {
+        ServerContext {
+            registry: Arc::new(ConnectionRegistry::default()),
+            storage,
+            metadata,
+            watch_parties: Arc::new(Mutex::new(WatchParties::default())),
+            friends_trending_cache: Arc::new(Mutex::new(TtlCache::new(FRIENDS_TRENDING_CACHE_CAPACITY))),
+            heartbeat,
+        }
+    }
+}
+
 struct WsServer {
     out: Sender,
+    registry: Arc<ConnectionRegistry>,
+    storage: StorageBackend,
+    metadata: Arc<dyn MetadataProvider>,
+    watch_parties: Arc<Mutex<WatchParties>>,
+    friends_trending_cache: Arc<FriendsTrendingCache>,
+    heartbeat: HeartbeatSettings,
+    heartbeat_expiry: Option<Timeout>,
+    last_activity: Instant,
 }
 
 impl Handler for WsServer {
     fn on_message(&mut self, msg: Message) -> WsResult<()> {
+        self.out.timeout(millis(self.heartbeat.timeout), heartbeat::EXPIRE)?;
+        self.record_activity();
         let raw_message = msg.into_text().unwrap();
         println!("The message from the client is {:#?}", &raw_message);
 
@@ -91,157 +282,250 @@ impl Handler for WsServer {
             return self.out.send("Invalid json value");
         }
 
-        let pool = POOL.clone();
-        let db_conn = pool.get().expect("Failed to get pooled connection");
-
-        let response = match json_maybe.unwrap()["action"].as_str().unwrap() {
-            "TakeUserMessage" => handle_user(&raw_message, &db_conn, &self.out),
-            "UserChangedOnlineStatus" => handle_online_status_change(&raw_message, &db_conn, &self.out),
-            "MakeFriendship" => handle_friendship(&raw_message, &db_conn, &self.out),
-            "ChangedVideo" => handle_vidoe_change(&raw_message, &db_conn, &self.out),
-            "FriendExclusion" => handle_friend_exclusion(&raw_message, &db_conn, &self.out),
-            "PING" => json!({"action": "PONG"}).to_string().to_owned(),
+        let json_value = json_maybe.unwrap();
+        let response = self.storage.with_storage(|storage| match json_value["action"].as_str().unwrap() {
+            "TakeUserMessage" => handle_user(&raw_message, storage, &self.out, &self.registry),
+            "UserChangedOnlineStatus" => handle_online_status_change(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "MakeFriendship" => handle_friendship(&raw_message, storage, &self.out, &self.registry),
+            "ChangedVideo" => handle_vidoe_change(&raw_message, storage, &self.out, &self.registry, self.metadata.as_ref()),
+            "FriendExclusion" => handle_friend_exclusion(&raw_message, storage, &self.out, &self.registry),
+            "FriendBlock" => handle_friend_block(&raw_message, storage, &self.out, &self.registry),
+            "SendChatMessage" => handle_send_chat_message(&raw_message, storage, &self.out, &self.registry),
+            "MarkChatMessagesRead" => handle_chat_messages_read(&raw_message, storage, &self.out, &self.registry),
+            "ReactToFriendVideo" => handle_react_to_friend_video(&raw_message, storage, &self.out, &self.registry),
+            "GetWatchHistory" => handle_get_watch_history(&raw_message, storage, &self.out, &self.registry),
+            "MarkNotificationsRead" => handle_notifications_read(&raw_message, storage, &self.out, &self.registry),
+            "ShareVideo" => handle_share_video(&raw_message, storage, &self.out, &self.registry, self.metadata.as_ref()),
+            "GetFriendSuggestions" => handle_get_friend_suggestions(&raw_message, storage, &self.out, &self.registry),
+            "SearchUsers" => handle_search_users(&raw_message, storage, &self.out, &self.registry),
+            "GetProfile" => handle_get_profile(&raw_message, storage, &self.out, &self.registry),
+            "SetDiscoverability" => handle_discoverability(&raw_message, storage, &self.out, &self.registry),
+            "CreateInviteCode" => handle_create_invite_code(&raw_message, storage, &self.out, &self.registry),
+            "GetFriendsTrending" => handle_get_friends_trending(&raw_message, storage, &self.out, &self.registry, &self.friends_trending_cache),
+            "GetRecommendations" => handle_get_recommendations(&raw_message, storage, &self.out, &self.registry),
+            "GetMyStats" => handle_get_my_stats(&raw_message, storage, &self.out, &self.registry),
+            "GetLeaderboard" => handle_get_leaderboard(&raw_message, storage, &self.out, &self.registry),
+            "GetFriendsActivity" => handle_get_friends_activity(&raw_message, storage, &self.out, &self.registry),
+            "PlaybackStateChanged" => handle_playback_state_change(&raw_message, storage, &self.out, &self.registry),
+            "LeftVideo" => handle_left_video(&raw_message, storage, &self.out, &self.registry),
+            "JoinFriendVideo" => handle_join_friend_video(&raw_message, storage, &self.out, &self.registry),
+            "StartWatchParty" => handle_start_watch_party(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "InviteToWatchParty" => handle_watch_party_invite(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "JoinWatchParty" => handle_join_watch_party(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "LeaveWatchParty" => handle_leave_watch_party(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "WatchPartyPlayback" => handle_watch_party_playback(&raw_message, storage, &self.out, &self.registry, &self.watch_parties),
+            "PING" => json!({"action": "PONG"}).to_string(),
             _ => "Unknown message type".to_owned(),
-        };
+        }).expect("Failed to get pooled connection");
         self.out.send(response)
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
         let client_conn_id = self.out.connection_id();
 
-        let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
-        let conn_metadata_maybe = connected_clients.get(&client_conn_id);
-
-        match conn_metadata_maybe {
-            Some(conn_metadata) => {
-                let broadcast_data = json!({
-                    "action": "TakeFriendOnlineStatus",
-                    "googleUserId": conn_metadata.googleUserId,
-                    "onlineState": false
-                });
-
-                for friend in conn_metadata.onlineFriends.iter() {
-                    let friend_conn_maybe: Option<&WsConnectedClientMetadata> =
-                        connected_clients.get(&friend.socketId);
-
-                    match friend_conn_maybe {
-                        Some(conn) => {
-                            conn.socket.send(broadcast_data.to_string());
-                        },
-                        None => println!("Done not")
-                    };
-                }
-
-                connected_clients.remove(&client_conn_id);
+        disconnect_client(&self.registry, &self.watch_parties, client_conn_id, &self.storage);
 
-                println!("connected_clients[ON_DISCONNECT]: {:?}", connected_clients);
-            }
-            _ => println!("Don't panic"),
-        };
         match code {
             CloseCode::Normal => println!("The client is done with the connection."),
             CloseCode::Away => println!("The client is leaving ..."),
             _ => println!("The client encountered an error: {}", reason),
         }
     }
+
+    fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+        self.out.timeout(millis(self.heartbeat.ping_interval), heartbeat::PING)?;
+        self.out.timeout(millis(self.heartbeat.timeout), heartbeat::EXPIRE)
+    }
+
+    fn on_timeout(&mut self, event: Token) -> WsResult<()> {
+        match event {
+            heartbeat::PING => {
+                self.out.ping(Utc::now().timestamp_millis().to_be_bytes().to_vec())?;
+                self.mark_away_if_idle();
+                self.out.timeout(millis(self.heartbeat.ping_interval), heartbeat::PING)
+            },
+            heartbeat::EXPIRE => {
+                // A sleeping laptop will not finish a closing handshake either, so evict before closing
+                println!("Socket {} missed its heartbeats, evicting it.", self.out.connection_id());
+                self.heartbeat_expiry = None;
+                disconnect_client(&self.registry, &self.watch_parties, self.out.connection_id(), &self.storage);
+                self.out.close(CloseCode::Away)
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn on_new_timeout(&mut self, event: Token, timeout: Timeout) -> WsResult<()> {
+        if event == heartbeat::EXPIRE {
+            if let Some(previous_expiry) = self.heartbeat_expiry.take() {
+                self.out.cancel(previous_expiry)?;
+            }
+            self.heartbeat_expiry = Some(timeout);
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> WsResult<Option<Frame>> {
+        if frame.opcode() == OpCode::Pong {
+            self.out.timeout(millis(self.heartbeat.timeout), heartbeat::EXPIRE)?;
+        }
+        Ok(Some(frame))
+    }
+}
+
+impl WsServer {
+    fn new(out: Sender, context: &ServerContext) -> WsServer {
+        WsServer {
+            out,
+            registry: context.registry.clone(),
+            storage: context.storage.clone(),
+            metadata: context.metadata.clone(),
+            watch_parties: context.watch_parties.clone(),
+            friends_trending_cache: context.friends_trending_cache.clone(),
+            heartbeat: context.heartbeat,
+            heartbeat_expiry: None,
+            last_activity: Instant::now()
+        }
+    }
+
+    // Any message counts as activity and brings an away client back
+    fn record_activity(&mut self) {
+        self.last_activity = Instant::now();
+
+        let came_back = self.registry.update(self.out.connection_id(), |conn_metadata| {
+            std::mem::replace(&mut conn_metadata.isAway, false)
+        });
+        if came_back == Some(true) {
+            broadcast_presence(&self.registry, self.out.connection_id());
+        }
+    }
+
+    fn mark_away_if_idle(&mut self) {
+        let last_activity = self.last_activity;
+        let went_away = self.registry.update(self.out.connection_id(), |conn_metadata| {
+            let is_playing = conn_metadata.currentVideo.as_ref()
+                .is_some_and(|current_video| current_video.playbackState == PlaybackState::Playing);
+
+            let went_away = !conn_metadata.isAway && is_idle(last_activity, Instant::now(), is_playing, self.heartbeat.away_after);
+            conn_metadata.isAway = conn_metadata.isAway || went_away;
+            went_away
+        });
+        if went_away == Some(true) {
+            broadcast_presence(&self.registry, self.out.connection_id());
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+// Tells the client's online friends it went away or came back
+fn broadcast_presence(registry: &ConnectionRegistry, client_conn_id: u32) {
+    registry.broadcast_to_online_friends(client_conn_id, |conn_metadata| {
+        json!({
+            "action": "TakeFriendPresence",
+            "googleUserId": conn_metadata.googleUserId,
+            "presence": if conn_metadata.isAway { "away" } else { "online" }
+        }).to_string()
+    });
+}
+
+// Broadcasts the client going offline and forgets it, sockets that never identified are skipped
+fn disconnect_client(registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>, client_conn_id: u32, storage: &StorageBackend) {
+    // The party still needs to know who is leaving
+    leave_watch_party(&registry.read(), watch_parties, client_conn_id);
+
+    let (conn_metadata, went_offline) = {
+        let mut connected_clients = registry.write();
+        let conn_metadata = match connected_clients.unregister(client_conn_id) {
+            Some(conn_metadata) => conn_metadata,
+            None => return
+        };
+        // The user stays online while another tab or device is still connected
+        let went_offline = !connected_clients.is_connected(&conn_metadata.googleUserId);
+        (conn_metadata, went_offline)
+    };
+
+    if went_offline {
+        let broadcast_data = json!({
+            "action": "TakeFriendOnlineStatus",
+            "googleUserId": conn_metadata.googleUserId,
+            "onlineState": false
+        });
+        registry.read().send_to_online_friends(&conn_metadata, &broadcast_data.to_string());
+
+        // Outside the lock, the other sockets need not wait on the database
+        record_last_seen(&conn_metadata.googleUserId, storage);
+    }
+}
+
+fn record_last_seen(google_user_id: &str, storage: &StorageBackend) {
+    let last_seen = storage.with_storage(|storage| {
+        storage.users.record_last_seen(google_user_id, Utc::now().naive_utc())
+    });
+    match last_seen {
+        Ok(Ok(())) => {},
+        Ok(Err(err)) => println!("Error saving last seen for {}: {:?}", google_user_id, err),
+        Err(err) => println!("No connection to record last seen: {:?}", err),
+    }
 }
 
-fn handle_user(json: &str, connection: &PgConnection, ws_client: &Sender) -> String {
+#[allow(non_snake_case, unused_variables)]
+fn handle_user(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
     let user_details_maybe: Result<TakeUserMessage, Error> =
         serde_json::from_str(json);
 
-    use tubepeek_server_rust::schema::userfriends::dsl::*;
-    use tubepeek_server_rust::schema::usermaster::dsl::*;
-
     match user_details_maybe {
         Ok(user_details) => {
             let google_user_id = &user_details.authData.uid.to_owned();
+            let invite_code = user_details.inviteCode.to_owned();
 
-            persist_user(user_details, connection);
+            let is_new_user = persist_user(user_details, storage);
+            let inviter = match invite_code {
+                Some(ref invite_code) if is_new_user => redeem_invite_code(invite_code, google_user_id, storage, registry),
+                _ => None
+            };
+            let pending_notifications = take_pending_notifications(google_user_id, storage.inbox);
             //--
-            let existing_friends : Vec<UserFriendEntity> = userfriends
-                .inner_join(usermaster.on(uid.eq(friend_google_uid)))
-                .filter(
-                    tubepeek_server_rust::schema::userfriends::dsl::user_google_uid
-                        .eq(google_user_id)
-                )
-                .load::<(tubepeek_server_rust::models::UserFriend, tubepeek_server_rust::models::Usermaster)>(connection)
-                .expect("Error loading userfriends joined to usermaster")
-                .iter()
-                .map(|result| UserFriendEntity::from(&result.0, &result.1))
-                .collect();
+            let mut existing_friends = load_friends(google_user_id, storage);
 
-            let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
+            // The database work happens outside the registry lock
+            let (online_friends, friends_current_video) = online_friends_activity(&existing_friends, &registry.read());
 
-            let mut is_connected_already = false;
-            for (conn_id, meta) in connected_clients.iter() {
-                if(meta.googleUserId == google_user_id.to_owned()) {
-                    is_connected_already = true;
-                    break;
-                }
-            }
-
-            let mut online_friends : Vec<WsOnlineFriend> = vec![];
-            let mut friends_current_video : Vec<WsFriendCurrentVideo> = vec![];
-
-            for friend in &existing_friends {
-                for (conn_id, meta) in connected_clients.iter() {
-                    if(meta.googleUserId == friend.friend_google_uid) {
-                        online_friends.push(WsOnlineFriend {
-                            socketId: meta.socketId,
-                            googleUserId: meta.googleUserId.to_string()
-                        });
-
-                        match &meta.currentVideo {
-                            Some(videoDetails) => {
-                                friends_current_video.push(WsFriendCurrentVideo {
-                                    googleUserId: meta.googleUserId.to_string(),
-                                    videoData: WsConnectedClientCurrentVideo {
-                                        videoUrl: videoDetails.videoUrl.to_string(),
-                                        title: videoDetails.title.to_string(),
-                                        thumbnail_url: videoDetails.thumbnail_url.to_string(),
-                                        timeStampInMilliseconds: videoDetails.timeStampInMilliseconds
-                                    },
-                                    friendData: CurrentVideoFriend {
-                                        full_name: friend.friend.full_name.to_owned(),
-                                        image_url: friend.friend.image_url.to_owned()
-                                    }
-                                });
-                            },
-                            None => {}
-                        };
-                    }
-                }
-            }
+            let offline_friends: Vec<&mut UserFriendEntity> = existing_friends.iter_mut()
+                .filter(|friend| !online_friends.iter().any(|online_friend| online_friend.googleUserId == friend.friend_google_uid))
+                .filter(|friend| friend.can_interact)
+                .collect();
+            fill_last_seen(offline_friends, storage);
 
-            if is_connected_already == false {
-                connected_clients.insert(ws_client.connection_id(),
-                    WsConnectedClientMetadata {
-                        socketId: ws_client.connection_id(),
-                        socket: ws_client.to_owned(),
-                        googleUserId: google_user_id.to_owned(),
-                        currentVideo: None,
-                        onlineFriends: Box::new(online_friends)
-                    },
-                );
-            } else {
-                let conn_metadata_maybe: Option<&mut WsConnectedClientMetadata> =
-                    connected_clients.get_mut(&ws_client.connection_id());
+            let mut connected_clients = registry.write();
 
-                match conn_metadata_maybe {
-                    Some(conn_metadata) => {
-                        conn_metadata.onlineFriends = Box::new(online_friends);
-                    },
-                    _ => println!("Don't panic kkkkkkkk"),
-                };
+            let client_conn_id = ws_client.connection_id();
+            match connected_clients.get_mut(client_conn_id) {
+                // The same socket identifying again keeps its video and presence
+                Some(conn_metadata) if conn_metadata.googleUserId == *google_user_id => {
+                    *conn_metadata.onlineFriends = online_friends;
+                },
+                _ => connected_clients.register(WsConnectedClientMetadata {
+                    socketId: client_conn_id,
+                    socket: ws_client.to_owned(),
+                    googleUserId: google_user_id.to_owned(),
+                    currentVideo: None,
+                    onlineFriends: Box::new(online_friends),
+                    isAway: false
+                }),
             }
+            drop(connected_clients);
 
-            println!("connected_clients: {:?}", connected_clients);
+            deliver_pending_chat_messages(&registry.read(), google_user_id, storage.inbox);
 
             let dataToReplyWith = json!({
                 "action": "TakeVideosBeingWatched",
                 "friendsOnYoutubeNow": friends_current_video,
-                "friendsOnTubePeek": &existing_friends
+                "friendsOnTubePeek": &existing_friends,
+                "notifications": pending_notifications,
+                "invitedBy": inviter
             });
 
             return dataToReplyWith.to_string();
@@ -256,36 +540,20 @@ fn handle_user(json: &str, connection: &PgConnection, ws_client: &Sender) -> Str
 }
 
 
-fn persist_user(user_details: TakeUserMessage, connection: &PgConnection) {
-    use tubepeek_server_rust::schema::usermaster::dsl::*;
-
+// Returns whether this is the user's first sign in
+fn persist_user(user_details: TakeUserMessage, storage: &Storage) -> bool {
     let now = Utc::now().naive_utc();
     let google_user_id = user_details.authData.uid.as_str();
 
-    let existing_user = usermaster
-        .filter(
-            tubepeek_server_rust::schema::usermaster::dsl::uid
-                .eq(google_user_id),
-        )
-        .limit(1)
-        .load::<Usermaster>(connection)
+    let existing_user = storage.users.find_user(google_user_id)
         .expect("Error loading users");
 
-    if existing_user.len() > 0 {
-        diesel::update(
-            usermaster.filter(
-                tubepeek_server_rust::schema::usermaster::dsl::uid
-                    .eq(google_user_id),
-            ),
-        )
-        .set((
-            tubepeek_server_rust::schema::usermaster::dsl::full_name
-                .eq(&user_details.authData.fullName),
-            tubepeek_server_rust::schema::usermaster::dsl::image_url
-                .eq(&user_details.authData.imageUrl),
-            tubepeek_server_rust::schema::usermaster::dsl::updated_at.eq(&now),
-        ))
-        .execute(connection);
+    if existing_user.is_some() {
+        // A stale name or picture is not worth failing the sign in over
+        if let Err(err) = storage.users.update_profile(google_user_id, &user_details.authData.fullName, &user_details.authData.imageUrl, now) {
+            println!("Error updating user {}: {:?}", google_user_id, err);
+        }
+        false
     } else {
         let new_user = NewUser {
             uid: google_user_id,
@@ -295,15 +563,94 @@ fn persist_user(user_details: TakeUserMessage, connection: &PgConnection) {
             created_at: now,
         };
 
-        let new_user_db_record = diesel::insert_into(usermaster)
-            .values(&new_user)
-            .get_result::<Usermaster>(connection)
+        storage.users.create_user(&new_user)
             .expect("Error saving new user");
+        true
+    }
+}
+
+// Friends who blocked the user are left out, the user's own blocks stay listed so they can be lifted
+fn load_friends(google_user_id: &str, storage: &Storage) -> Vec<UserFriendEntity> {
+    storage.friendships.friends_of(google_user_id)
+        .expect("Error loading userfriends joined to usermaster")
+        .iter()
+        .filter(|(_, friend_row_back, _)| !friend_row_back.as_ref().is_some_and(|friend_row_back| friend_row_back.is_friend_blocked))
+        .map(|(user_friend_row, friend_row_back, friend)| UserFriendEntity::from(user_friend_row, friend_row_back.as_ref(), friend))
+        .collect()
+}
+
+// Every connected socket of the given friends, and the videos playing on them. Excluded and blocked
+// friends are skipped, the online friends are who the user's broadcasts fan out to.
+#[allow(non_snake_case)]
+fn online_friends_activity(friends: &[UserFriendEntity], connected_clients: &Connections) -> (Vec<WsOnlineFriend>, Vec<WsFriendCurrentVideo>) {
+    let mut online_friends : Vec<WsOnlineFriend> = vec![];
+    let mut friends_current_video : Vec<WsFriendCurrentVideo> = vec![];
+
+    for friend in friends.iter().filter(|friend| friend.can_interact) {
+        for meta in connected_clients.connections_of(&friend.friend_google_uid) {
+            online_friends.push(WsOnlineFriend {
+                socketId: meta.socketId,
+                googleUserId: meta.googleUserId.to_string()
+            });
+
+            if let Some(videoDetails) = &meta.currentVideo {
+                friends_current_video.push(WsFriendCurrentVideo {
+                    googleUserId: meta.googleUserId.to_string(),
+                    videoData: videoDetails.clone(),
+                    friendData: CurrentVideoFriend {
+                        full_name: friend.friend.full_name.to_owned(),
+                        image_url: friend.friend.image_url.to_owned()
+                    }
+                });
+            }
+        }
     }
+
+    (online_friends, friends_current_video)
+}
+
+// A snapshot of what friends are up to for an already identified socket, without going through handle_user again
+fn handle_get_friends_activity(_json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let google_user_id = match registry.user_of(ws_client.connection_id()) {
+        Some(google_user_id) => google_user_id,
+        None => {
+            return json!({
+                "action": "ERROR",
+                "message": "Send TakeUserMessage before asking for friends activity"
+            }).to_string();
+        }
+    };
+
+    // Like last seen, presence and videos are only shared while neither side has excluded or blocked the other
+    let existing_friends: Vec<UserFriendEntity> = load_friends(&google_user_id, storage).into_iter()
+        .filter(|friend| friend.can_interact)
+        .collect();
+
+    let connected_clients = registry.read();
+    let (_, friends_current_video) = online_friends_activity(&existing_friends, &connected_clients);
+
+    // A friend on several sockets is away only when all of them are
+    let friends_presence: Vec<JsonValue> = existing_friends.iter()
+        .filter(|friend| connected_clients.is_connected(&friend.friend_google_uid))
+        .map(|friend| {
+            let is_away = connected_clients.connections_of(&friend.friend_google_uid).all(|meta| meta.isAway);
+            json!({
+                "googleUserId": friend.friend_google_uid,
+                "presence": if is_away { "away" } else { "online" }
+            })
+        })
+        .collect();
+
+    json!({
+        "action": "TakeFriendsActivity",
+        "onlineFriends": friends_presence,
+        "friendsOnYoutubeNow": friends_current_video
+    }).to_string()
 }
 
 
-fn handle_online_status_change(json: &str, connection: &PgConnection, ws_client: &Sender) -> String {
+#[allow(unused_variables)]
+fn handle_online_status_change(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
     let online_status_maybe: Result<OnlineStatusChange, Error> =
         serde_json::from_str(json);
 
@@ -318,30 +665,11 @@ fn handle_online_status_change(json: &str, connection: &PgConnection, ws_client:
                 "onlineState": online_status
             });
 
-            let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
-
-            let conn_metadata_maybe: Option<&WsConnectedClientMetadata> =
-                connected_clients.get(&ws_client.connection_id());
+            registry.broadcast_to_online_friends(ws_client.connection_id(), |_| broadcast_data.to_string());
 
-            match conn_metadata_maybe {
-                Some(conn_metadata) => {
-                    for friend in conn_metadata.onlineFriends.iter() {
-                        let friend_conn_maybe: Option<&WsConnectedClientMetadata> =
-                            connected_clients.get(&friend.socketId);
-
-                        match friend_conn_maybe {
-                            Some(conn) => {
-                                conn.socket.send(broadcast_data.to_string());
-                            },
-                            None => println!("Done not")
-                        };
-                    }
-                },
-                _ => println!("Don't panic kkkkkkkk"),
-            };
-
-            if(!online_status) {
-                connected_clients.remove(&ws_client.connection_id());
+            if !online_status {
+                leave_watch_party(&registry.read(), watch_parties, ws_client.connection_id());
+                registry.write().unregister(ws_client.connection_id());
             }
         },
         Err(err_msg) => {
@@ -352,114 +680,15 @@ fn handle_online_status_change(json: &str, connection: &PgConnection, ws_client:
     "{}".to_owned()
 }
 
-fn handle_friendship(json: &str, connection: &PgConnection, ws_client: &Sender) -> String {
-    use tubepeek_server_rust::schema::usermaster::dsl::*;
-    use tubepeek_server_rust::schema::userfriends::dsl::*;
-
+#[allow(unused_variables)]
+fn handle_friendship(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
     let make_friendship_maybe: Result<MakeFriendshipMessage, Error> =
         serde_json::from_str(json);
 
     match make_friendship_maybe {
         Ok(make_friendship) => {
-            let google_user_id = &make_friendship.googleUserId.to_owned();
-            let friend_google_user_id = &make_friendship.friendGoogleUserId.to_owned();
-            let now = Utc::now().naive_utc();
-
-            let does_friend_exist = userfriends
-                .filter(
-                    tubepeek_server_rust::schema::userfriends::dsl::user_google_uid
-                        .eq(&google_user_id)
-                        .and(tubepeek_server_rust::schema::userfriends::dsl::friend_google_uid
-                            .eq(&friend_google_user_id)),
-                )
-                .limit(1)
-                .load::<UserFriend>(connection)
-                .expect("Error loading user friend");
-
-            if(does_friend_exist.len() == 0) {
-                let new_friend = NewUserFriend {
-                    user_google_uid: google_user_id,
-                    friend_google_uid: friend_google_user_id,
-                    is_friend_excluded: false,
-                    created_at: now,
-                };
-
-                let new_social_id_db_record = diesel::insert_into(userfriends)
-                    .values(&new_friend)
-                    .execute(connection)
-                    .expect("Error saving new friend");
-            }
-            //--
-            let does_reverse_friend_exist = userfriends
-                .filter(
-                    tubepeek_server_rust::schema::userfriends::dsl::user_google_uid
-                        .eq(&friend_google_user_id)
-                        .and(tubepeek_server_rust::schema::userfriends::dsl::friend_google_uid
-                            .eq(&google_user_id)),
-                )
-                .limit(1)
-                .load::<UserFriend>(connection)
-                .expect("Error loading user reverse friend");
-
-            if(does_reverse_friend_exist.len() == 0) {
-                let reverse_new_friend = NewUserFriend {
-                    user_google_uid: friend_google_user_id,
-                    friend_google_uid: google_user_id,
-                    is_friend_excluded: false,
-                    created_at: now,
-                };
-
-                diesel::insert_into(userfriends)
-                    .values(&reverse_new_friend)
-                    .execute(connection)
-                    .expect("Error saving new reverse friend");
-            }
-            //--
-            let current_user = usermaster
-                .filter(
-                    tubepeek_server_rust::schema::usermaster::dsl::uid
-                        .eq(google_user_id),
-                )
-                .load::<Usermaster>(connection)
-                .expect("Error loading current user");
-
-            let friend_user = usermaster
-                .filter(
-                    tubepeek_server_rust::schema::usermaster::dsl::uid
-                        .eq(friend_google_user_id),
-                )
-                .load::<Usermaster>(connection)
-                .expect("Error loading friend user");
-
-            if (current_user.len() > 0 || friend_user.len() > 0) {
-                let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
-
-                for (conn_id, meta) in connected_clients.iter() {
-                    if(current_user.len() > 0 && meta.googleUserId == *friend_google_user_id) {
-                        let broadcast_data = json!({
-                            "action": "NewFriendOnTubePeek",
-                            "friendDetails": {
-                                "googleUserId": *google_user_id,
-                                "fullName": current_user[0].full_name,
-                                "imageUrl": current_user[0].image_url
-                            }
-                        });
-
-                        meta.socket.send(broadcast_data.to_string());
-                    }
-                    if(friend_user.len() > 0 && meta.googleUserId == *google_user_id) {
-                        let broadcast_data = json!({
-                            "action": "NewFriendOnTubePeek",
-                            "friendDetails": {
-                                "googleUserId": *friend_google_user_id,
-                                "fullName": friend_user[0].full_name,
-                                "imageUrl": friend_user[0].image_url
-                            }
-                        });
-
-                        meta.socket.send(broadcast_data.to_string());
-                    }
-                }
+            if let Err(err_msg) = make_friendship_rows(&make_friendship.googleUserId, &make_friendship.friendGoogleUserId, storage, registry) {
+                return json!({"action": "ERROR", "message": err_msg}).to_string();
             }
         },
         Err(err_msg) => {
@@ -470,128 +699,142 @@ fn handle_friendship(json: &str, connection: &PgConnection, ws_client: &Sender)
     "{}".to_owned()
 }
 
-fn handle_vidoe_change(json: &str, connection: &PgConnection, ws_client: &Sender) -> String {
-    let video_change_maybe: Result<VideoChangeMessage, Error> = serde_json::from_str(json);
+// Friends who are not on TubePeek yet have no usermaster row to reference, they come in through invite codes
+fn make_friendship_rows(google_user_id: &str, friend_google_user_id: &str, storage: &Storage, registry: &ConnectionRegistry) -> Result<(), &'static str> {
+    if google_user_id == friend_google_user_id {
+        return Err("Cannot befriend yourself");
+    }
+    let now = Utc::now().naive_utc();
+
+    let current_user = storage.users.find_user(google_user_id)
+        .expect("Error loading current user");
+
+    let friend_user = storage.users.find_user(friend_google_user_id)
+        .expect("Error loading friend user");
+
+    let (current_user, friend_user) = match (current_user, friend_user) {
+        (Some(current_user), Some(friend_user)) => (current_user, friend_user),
+        _ => return Err("Unknown user, send them an invite code instead")
+    };
+    //--
+    let does_friend_exist = storage.friendships.find_friendship(google_user_id, friend_google_user_id)
+        .expect("Error loading user friend");
+
+    if does_friend_exist.is_none() {
+        let new_friend = NewUserFriend {
+            user_google_uid: google_user_id,
+            friend_google_uid: friend_google_user_id,
+            is_friend_excluded: false,
+            created_at: now,
+        };
+
+        storage.friendships.create_friendship(&new_friend)
+            .expect("Error saving new friend");
+    }
+    //--
+    let does_reverse_friend_exist = storage.friendships.find_friendship(friend_google_user_id, google_user_id)
+        .expect("Error loading user reverse friend");
+
+    if does_reverse_friend_exist.is_none() {
+        let reverse_new_friend = NewUserFriend {
+            user_google_uid: friend_google_user_id,
+            friend_google_uid: google_user_id,
+            is_friend_excluded: false,
+            created_at: now,
+        };
+
+        storage.friendships.create_friendship(&reverse_new_friend)
+            .expect("Error saving new reverse friend");
+    }
+    //--
+    let connected_clients = registry.read();
+
+    let broadcast_data = json!({
+        "action": "NewFriendOnTubePeek",
+        "friendDetails": {
+            "googleUserId": google_user_id,
+            "fullName": current_user.full_name,
+            "imageUrl": current_user.image_url
+        }
+    });
+    notify_user(&connected_clients, friend_google_user_id, &broadcast_data, storage.inbox);
+
+    let broadcast_data = json!({
+        "action": "NewFriendOnTubePeek",
+        "friendDetails": {
+            "googleUserId": friend_google_user_id,
+            "fullName": friend_user.full_name,
+            "imageUrl": friend_user.image_url
+        }
+    });
+    notify_user(&connected_clients, google_user_id, &broadcast_data, storage.inbox);
 
-    use tubepeek_server_rust::schema::usermaster::dsl::*;
-    use reqwest::Error as ReqWestError;
+    Ok(())
+}
+
+#[allow(unused_variables, unused_must_use)]
+fn handle_vidoe_change(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, metadata: &dyn MetadataProvider) -> String {
+    let video_change_maybe: Result<VideoChangeMessage, Error> = serde_json::from_str(json);
 
     match video_change_maybe {
         Ok(video_change) => {
-            let video_url = video_change.videoUrl.as_str();
-            let google_user_id = video_change.googleUserId.as_str();
-            let youtube_api_key = env::var("YOUTUBE_API_KEY").unwrap();
+            // Watches also become the user's last seen and last video, shown to their friends
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
 
-            let youtube_video_id_maybe: Option<String> = get_youtube_videoid(video_url);
-            if let None = youtube_video_id_maybe {
+            let video_url_maybe: Option<VideoUrl> = parse_video_url(&video_change.videoUrl);
+            if video_url_maybe.is_none() {
                 return json!({
                     "action": "ERROR",
-                    "message": "Invalid youtube id"
+                    "message": "Invalid video url"
                 }).to_string();
             }
-            let youtubeVideoId = youtube_video_id_maybe.unwrap();
+            let video_url = video_url_maybe.unwrap();
+
+            let video_metadata = match metadata.video_metadata(&video_url) {
+                Ok(video_metadata) => video_metadata,
+                Err(err_msg) => {
+                    return json!({
+                        "action": "ERROR",
+                        "message": err_msg
+                    }).to_string();
+                }
+            };
 
-//            let youtube_query_url = format!(
-//                "http://www.youtube.com{}{}", "/oembed?format=json&url=", video_url
-//            );
-            let youtube_query_url = format!(
-                "https://www.googleapis.com/youtube/v3/videos?id={}&key={}&part=snippet", youtubeVideoId, youtube_api_key
+            let mut current_video = WsConnectedClientCurrentVideo::from(
+                &video_url, &video_metadata, Utc::now().timestamp_millis()
             );
+            let resolved_playlist = resolve_playlist(&video_url, storage, metadata);
+            current_video.playlist = resolved_playlist.as_ref().map(|(current_playlist, _)| current_playlist.clone());
+            let client_conn_id = ws_client.connection_id();
 
-            let now = Utc::now().naive_utc();
+            let is_registered = registry.update(client_conn_id, |conn_metadata| {
+                conn_metadata.currentVideo = Some(current_video.clone());
+            }).is_some();
 
-            let response_maybe = reqwest::blocking::get(youtube_query_url.as_str());
-            match response_maybe {
-                Ok(response) => {
-                    let youtube_response_maybe = response.json::<YoutubeVideoResponse>();
-                    if let Err(_err) = youtube_response_maybe {
-                        return json!({
-                            "action": "ERROR",
-                            "message": "Invalid youtube json response format"
-                        }).to_string();
-                    }
+            let friend_user = storage.users.find_user(&google_user_id)
+                .expect("Error loading friend_user");
 
-                    let decoded_video_details = youtube_response_maybe.unwrap();
-//                    let video_title = decoded_video_details.title;
-//                    let video_thumbnail = decoded_video_details.thumbnail_url;
-                    let video_title = &decoded_video_details.items.get(0).unwrap().snippet.title;
-                    let video_thumbnail = &decoded_video_details.items.get(0).unwrap().snippet.thumbnails.default.url;
-
-                    let client_conn_id = ws_client.connection_id();
-
-                    {
-                        let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
-
-                        let conn_metadata_maybe: Option<&mut WsConnectedClientMetadata> =
-                            connected_clients.get_mut(&client_conn_id);
-
-                        match conn_metadata_maybe {
-                            Some(conn_metadata) => {
-                                conn_metadata.currentVideo = Some(WsConnectedClientCurrentVideo {
-                                    videoUrl: video_url.to_string(),
-                                    title: video_title.to_string(),
-                                    thumbnail_url: video_thumbnail.to_string(),
-                                    timeStampInMilliseconds: now.timestamp_millis()
-                                });
-                            },
-                            _ => println!("Don't panic kkkkkkkk"),
-                        };
+            if let (true, Some(friend_user)) = (is_registered, friend_user) {
+                let broadcast_data = json!({
+                    "action": "TakeFriendVideoChange",
+                    "googleUserId": google_user_id,
+                    "videoData": current_video,
+                    "friendData": {
+                        "full_name": friend_user.full_name,
+                        "image_url": friend_user.image_url
                     }
+                });
+                registry.broadcast_to_online_friends(client_conn_id, |_| broadcast_data.to_string());
+            }
 
-                    let mut connected_clients = WS_CONNECTED_CLIENTS.lock().unwrap();
-
-                    let conn_metadata_maybe: Option<&WsConnectedClientMetadata> =
-                        connected_clients.get(&client_conn_id);
-
-                    match conn_metadata_maybe {
-                        Some(conn_metadata) => {
-                            let friend_user = usermaster
-                                .filter(
-                                    tubepeek_server_rust::schema::usermaster::dsl::uid
-                                        .eq(google_user_id),
-                                )
-                                .limit(1)
-                                .load::<Usermaster>(connection)
-                                .expect("Error loading friend_user");
-
-                            if friend_user.len() > 0 {
-                                let broadcast_data = json!({
-                                    "action": "TakeFriendVideoChange",
-                                    "googleUserId": google_user_id,
-                                    "videoData": {
-                                        "videoUrl": video_url,
-                                        "title": video_title,
-                                        "thumbnail_url": video_thumbnail,
-                                        "timeStampInMilliseconds": now.timestamp_millis()
-                                    },
-                                    "friendData": {
-                                        "full_name": friend_user[0].full_name,
-                                        "image_url": friend_user[0].image_url
-                                    }
-                                });
-
-                                for friend in conn_metadata.onlineFriends.iter() {
-                                    let friend_conn_maybe: Option<&WsConnectedClientMetadata> =
-                                        connected_clients.get(&friend.socketId);
-
-                                    match friend_conn_maybe {
-                                        Some(conn) => {
-                                            conn.socket.send(broadcast_data.to_string());
-                                        },
-                                        None => println!("Done not")
-                                    };
-                                }
-                            }
-                        },
-                        _ => println!("Don't panic!"),
-                    };
+            let video_db_record = persist_video_watched(&google_user_id, &video_url, &video_metadata, storage);
 
-                    persist_video_watched(google_user_id, video_url, video_title.as_str(), connection);
-                },
-                _ => {
-                    println!("Invalid youtube response.");
-                }
-            };
+            if let (Some(video_db_record), Some((current_playlist, Some(playlist_db_record)))) = (video_db_record, resolved_playlist) {
+                persist_playlist_video(&playlist_db_record, &video_db_record, current_playlist.position, storage);
+            }
         },
         Err(err_msg) => {
             println!("Invalid video change.");
@@ -600,131 +843,1659 @@ fn handle_vidoe_change(json: &str, connection: &PgConnection, ws_client: &Sender
     "{}".to_owned()
 }
 
-fn persist_video_watched(google_user_id: &str, videoUrl: &str, videoTitle: &str, connection: &PgConnection) {
-    use tubepeek_server_rust::schema::usermaster::dsl::*;
-    use tubepeek_server_rust::schema::videos::dsl::*;
-    use tubepeek_server_rust::schema::uservideos::dsl::*;
+fn persist_video_watched(google_user_id: &str, watched_video_url: &VideoUrl, video_metadata: &VideoMetadata, storage: &Storage) -> Option<Video> {
+    let existing_user = storage.users.find_user(google_user_id)
+        .expect("Error loading user")?;
+
+    let video_db_record = persist_video(watched_video_url, video_metadata, storage);
+
+    storage.videos.record_watch(existing_user.id, video_db_record.id, Utc::now().naive_utc())
+        .expect("Error saving watched video");
+
+    Some(video_db_record)
+}
 
+// Playlist details come from the playlists table while fresh, the metadata provider otherwise. The position
+// comes from the url, then from an earlier sighting of the video in the playlist, then from the provider.
+fn resolve_playlist(watched_video_url: &VideoUrl, storage: &Storage, metadata: &dyn MetadataProvider) -> Option<(WsCurrentPlaylist, Option<Playlist>)> {
+    let playlist_external_id = watched_video_url.playlist_id.as_ref()?;
+    let platform_name = watched_video_url.platform.as_str();
     let now = Utc::now().naive_utc();
 
-    let youtube_video_id_maybe: Option<String> = get_youtube_videoid(videoUrl);
-    if let None = youtube_video_id_maybe {
-        println!("Invalid youtube url metadata");
-        return;
-    }
+    let stored_playlist = storage.videos.find_playlist(platform_name, playlist_external_id)
+        .expect("Error loading playlist");
 
-    let youtubeVideoId = youtube_video_id_maybe.unwrap();
+    let is_fresh = stored_playlist.as_ref().is_some_and(|stored| {
+        now - stored.updated_at.unwrap_or(stored.created_at) < chrono::Duration::minutes(PLAYLIST_METADATA_TTL_MINUTES)
+    });
 
-    let save_user_video = |userId: i64, videoId: i64, now: &NaiveDateTime| {
-        let new_user_video = NewUserVideo {
-            user_id: userId,
-            video_id: videoId,
-            created_at: *now,
-        };
+    let playlist_db_record = if is_fresh {
+        stored_playlist
+    } else {
+        match metadata.playlist_metadata(watched_video_url.platform, playlist_external_id) {
+            Ok(playlist_metadata) => Some(persist_playlist(watched_video_url, &playlist_metadata, storage)),
+            Err(err_msg) => {
+                println!("No metadata for playlist {}: {}", playlist_external_id, err_msg);
+                stored_playlist
+            }
+        }
+    };
 
-        diesel::insert_into(uservideos)
-            .values(&new_user_video)
-            .execute(connection)
-            .expect("Error saving new user video");
+    let stored_position = || -> Option<u32> {
+        let playlist_db_record = playlist_db_record.as_ref()?;
+        storage.videos.playlist_position(playlist_db_record.id, platform_name, &watched_video_url.external_id)
+            .expect("Error loading playlist position")
+            .map(|stored_position| stored_position as u32)
     };
 
-    let existing_user = usermaster
-        .filter(
-            tubepeek_server_rust::schema::usermaster::dsl::uid
-                .eq(google_user_id),
-        )
-        .load::<Usermaster>(connection)
-        .expect("Error loading user");
-
-    if (existing_user.len() > 0) {
-        let existing_video = videos
-            .filter(
-                tubepeek_server_rust::schema::videos::dsl::youtube_video_id
-                    .eq(&youtubeVideoId)
-            )
-            .load::<Video>(connection)
-            .expect("Error loading video");
-
-        if (existing_video.len() == 0) {
-            let new_video = NewVideo {
-                video_url: videoUrl,
-                youtube_video_id: &youtubeVideoId,
-                video_title: videoTitle,
+    let playlist_position = watched_video_url.playlist_index
+        .or_else(stored_position)
+        .or_else(|| {
+            // Unknown playlists are not worth a second request
+            playlist_db_record.as_ref()?;
+            metadata.playlist_position(watched_video_url.platform, playlist_external_id, &watched_video_url.external_id).ok()
+        });
+
+    let current_playlist = WsCurrentPlaylist {
+        playlistId: playlist_external_id.to_string(),
+        title: playlist_db_record.as_ref().map(|playlist| playlist.title.to_string()),
+        channelTitle: playlist_db_record.as_ref().and_then(|playlist| playlist.channel_title.clone()),
+        thumbnail_url: playlist_db_record.as_ref().and_then(|playlist| playlist.thumbnail_url.clone()),
+        itemCount: playlist_db_record.as_ref().and_then(|playlist| playlist.item_count),
+        position: playlist_position,
+    };
+
+    Some((current_playlist, playlist_db_record))
+}
+
+fn persist_playlist(resolved_video_url: &VideoUrl, playlist_metadata: &PlaylistMetadata, storage: &Storage) -> Playlist {
+    let now = Utc::now().naive_utc();
+    let platform_name = resolved_video_url.platform.as_str();
+    let playlist_external_id = resolved_video_url.playlist_id.as_deref().unwrap_or_default();
+
+    let existing_playlist = storage.videos.find_playlist(platform_name, playlist_external_id)
+        .expect("Error loading playlist");
+
+    match existing_playlist {
+        Some(existing_playlist) => {
+            let playlist_metadata_update = PlaylistMetadataUpdate {
+                title: &playlist_metadata.title,
+                channel_id: Some(&playlist_metadata.channel_id),
+                channel_title: Some(&playlist_metadata.channel_title),
+                item_count: playlist_metadata.item_count,
+                thumbnail_url: Some(&playlist_metadata.thumbnail_url),
+                updated_at: now,
+            };
+
+            storage.videos.update_playlist_metadata(existing_playlist.id, &playlist_metadata_update)
+                .expect("Error updating playlist metadata")
+        },
+        None => {
+            let new_playlist = NewPlaylist {
+                platform: platform_name,
+                external_id: playlist_external_id,
+                title: &playlist_metadata.title,
+                channel_id: Some(&playlist_metadata.channel_id),
+                channel_title: Some(&playlist_metadata.channel_title),
+                item_count: playlist_metadata.item_count,
+                thumbnail_url: Some(&playlist_metadata.thumbnail_url),
                 created_at: now,
             };
 
-            let new_video_db_record = diesel::insert_into(videos)
-                .values(&new_video)
-                .get_result::<Video>(connection)
-                .expect("Error saving new video");
+            storage.videos.create_playlist(&new_playlist)
+                .expect("Error saving new playlist")
+        }
+    }
+}
+
+// Remembers where the video sits in the playlist, playlists get reordered so the latest sighting wins
+fn persist_playlist_video(playlist_db_record: &Playlist, video_db_record: &Video, playlist_position: Option<u32>, storage: &Storage) {
+    let new_playlist_video = NewPlaylistVideo {
+        playlist_id: playlist_db_record.id,
+        video_id: video_db_record.id,
+        position: playlist_position.map(|playlist_position| playlist_position as i32),
+        created_at: Utc::now().naive_utc(),
+    };
 
-            save_user_video(existing_user[0].id, new_video_db_record.id, &now);
-        } else {
-            let existing_user_video = uservideos
-                .filter(
-                    tubepeek_server_rust::schema::uservideos::dsl::user_id
-                        .eq(existing_user[0].id)
-                        .and(tubepeek_server_rust::schema::uservideos::dsl::video_id
-                            .eq(existing_video[0].id))
-                )
-                .load::<UserVideo>(connection)
-                .expect("Error loading user video");
+    storage.videos.save_playlist_video(&new_playlist_video)
+        .expect("Error saving playlist video");
+}
 
-            if (existing_user_video.len() == 0) {
-                save_user_video(existing_user[0].id, existing_video[0].id, &now);
-            }
+// Inserts the video, or refreshes the metadata of the row already keyed by (platform, external_id)
+fn persist_video(resolved_video_url: &VideoUrl, video_metadata: &VideoMetadata, storage: &Storage) -> Video {
+    let now = Utc::now().naive_utc();
+
+    let existing_video = storage.videos.find_video(resolved_video_url.platform.as_str(), &resolved_video_url.external_id)
+        .expect("Error loading video");
+
+    match existing_video {
+        None => {
+            let new_video = NewVideo {
+                video_url: &resolved_video_url.canonical_url(),
+                external_id: &resolved_video_url.external_id,
+                video_title: &video_metadata.title,
+                channel_id: Some(&video_metadata.channel_id),
+                channel_title: Some(&video_metadata.channel_title),
+                published_at: video_metadata.published_at,
+                category_id: video_metadata.category_id.as_deref(),
+                thumbnail_default_url: Some(&video_metadata.thumbnails.default),
+                thumbnail_medium_url: video_metadata.thumbnails.medium.as_deref(),
+                thumbnail_high_url: video_metadata.thumbnails.high.as_deref(),
+                thumbnail_maxres_url: video_metadata.thumbnails.maxres.as_deref(),
+                duration_seconds: video_metadata.duration_seconds,
+                is_live_broadcast: video_metadata.is_live_broadcast,
+                platform: resolved_video_url.platform.as_str(),
+                created_at: now,
+            };
+
+            storage.videos.create_video(&new_video)
+                .expect("Error saving new video")
+        },
+        Some(existing_video) => {
+            // Titles, thumbnails and the live flag change over a video's lifetime
+            let video_metadata_update = VideoMetadataUpdate {
+                video_title: &video_metadata.title,
+                channel_id: Some(&video_metadata.channel_id),
+                channel_title: Some(&video_metadata.channel_title),
+                published_at: video_metadata.published_at,
+                category_id: video_metadata.category_id.as_deref(),
+                thumbnail_default_url: Some(&video_metadata.thumbnails.default),
+                thumbnail_medium_url: video_metadata.thumbnails.medium.as_deref(),
+                thumbnail_high_url: video_metadata.thumbnails.high.as_deref(),
+                thumbnail_maxres_url: video_metadata.thumbnails.maxres.as_deref(),
+                duration_seconds: video_metadata.duration_seconds,
+                is_live_broadcast: video_metadata.is_live_broadcast,
+                updated_at: now,
+            };
+
+            storage.videos.update_video_metadata(existing_video.id, &video_metadata_update)
+                .expect("Error updating video metadata")
         }
     }
 }
 
+fn handle_playback_state_change(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let playback_state_maybe: Result<PlaybackStateChangedMessage, Error> = serde_json::from_str(json);
+
+    match playback_state_maybe {
+        Ok(playback_state_change) => {
+            let client_conn_id = ws_client.connection_id();
+            let now = Utc::now().timestamp_millis();
+
+            let is_watching = registry.update(client_conn_id, |conn_metadata| {
+                match conn_metadata.currentVideo.as_mut() {
+                    Some(current_video) => {
+                        current_video.playbackState = playback_state_change.state;
+                        current_video.positionInSeconds = playback_state_change.positionInSeconds;
+                        current_video.positionTimeStampInMilliseconds = now;
+                        true
+                    },
+                    None => false
+                }
+            }).unwrap_or(false);
+
+            if !is_watching {
+                return json!({
+                    "action": "ERROR",
+                    "message": "No video is being watched"
+                }).to_string();
+            }
 
-fn handle_friend_exclusion(json: &str, connection: &PgConnection, ws_client: &Sender) -> String {
-    let friend_exclusion_maybe: Result<FriendExclusionMessage, Error> = serde_json::from_str(json);
+            if playback_state_change.state == PlaybackState::Ended {
+                clear_current_video(registry, client_conn_id, "ended");
+            } else {
+                registry.broadcast_to_online_friends(client_conn_id, |conn_metadata| {
+                    json!({
+                        "action": "TakeFriendPlaybackState",
+                        "googleUserId": conn_metadata.googleUserId,
+                        "state": playback_state_change.state,
+                        "positionInSeconds": playback_state_change.positionInSeconds,
+                        "timeStampInMilliseconds": now
+                    }).to_string()
+                });
+            }
+        },
+        Err(_err) => {
+            println!("Invalid playback state change.");
+        }
+    };
 
-    use tubepeek_server_rust::schema::userfriends::dsl::*;
+    "{}".to_owned()
+}
 
-    match friend_exclusion_maybe {
-        Ok(friend_exclusion) => {
-            diesel::update(
-                userfriends.filter(
-                    tubepeek_server_rust::schema::userfriends::dsl::user_google_uid
-                        .eq(friend_exclusion.googleUserId)
-                        .and(tubepeek_server_rust::schema::userfriends::dsl::friend_google_uid
-                            .eq(friend_exclusion.friendGoogleUserId)),
-                ),
-            )
-            .set((
-                tubepeek_server_rust::schema::userfriends::dsl::is_friend_excluded
-                    .eq(friend_exclusion.exclude)
-            ))
-            .execute(connection);
+fn handle_left_video(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let left_video_maybe: Result<LeftVideoMessage, Error> = serde_json::from_str(json);
+
+    match left_video_maybe {
+        Ok(_left_video) => {
+            clear_current_video(registry, ws_client.connection_id(), "left");
         },
-        Err(err_msg) => {
-            println!("Invalid friend exclusion.");
+        Err(_err) => {
+            println!("Invalid left video.");
         }
-    }
+    };
 
     "{}".to_owned()
 }
 
+// Handlers act as the user the socket identified as, whoever the message claims to come from.
+// The error is the reply for sockets that have not sent TakeUserMessage yet.
+fn identified_user(registry: &ConnectionRegistry, ws_client: &Sender) -> Result<String, String> {
+    registry.user_of(ws_client.connection_id()).ok_or_else(|| {
+        json!({
+            "action": "ERROR",
+            "message": "Send TakeUserMessage first"
+        }).to_string()
+    })
+}
 
-fn main() {
-    println!("Tubepeek server up and running ...");
-    // dotenv().ok();
+fn handle_join_friend_video(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let join_friend_video_maybe: Result<JoinFriendVideoMessage, Error> = serde_json::from_str(json);
 
-    let server_ip = env::var("SELF_SERVER_HOST")
-        .expect("SELF_SERVER_HOST must be set");
+    match join_friend_video_maybe {
+        Ok(join_friend_video) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
 
-    let server_port = env::var("PORT")
-        .expect("PORT must be set");
+            match can_interact(&google_user_id, &join_friend_video.friendGoogleUserId, storage.friendships) {
+                Ok(true) => {},
+                Ok(false) => return json!({"action": "ERROR", "message": "Not friends"}).to_string(),
+                Err(reply) => return reply
+            }
 
-    env::var("YOUTUBE_API_KEY")
-        .expect("YOUTUBE_API_KEY must be set");
+            let connected_clients = registry.read();
 
+            // With several tabs open, follow whichever one most recently reported playback
+            let friend_current_video = connected_clients.connections_of(&join_friend_video.friendGoogleUserId)
+                .filter_map(|meta| meta.currentVideo.as_ref())
+                .max_by_key(|current_video| current_video.positionTimeStampInMilliseconds);
 
-    let ws_mount_point = format!("{}:{}", server_ip, server_port);
+            match friend_current_video {
+                Some(current_video) => {
+                    let now = Utc::now().timestamp_millis();
+                    let position_in_seconds = if current_video.isLiveBroadcast {
+                        0.0
+                    } else {
+                        current_video.estimated_position(now)
+                    };
+                    let video_url_at_position = parse_video_url(&current_video.videoUrl)
+                        .map(|mut video_url| {
+                            if let Some(playlist) = &current_video.playlist {
+                                video_url.playlist_id = Some(playlist.playlistId.to_string());
+                                video_url.playlist_index = playlist.position;
+                            }
+                            video_url.follow_along_url(position_in_seconds as i64)
+                        })
+                        .unwrap_or_else(|| current_video.videoUrl.to_string());
+
+                    json!({
+                        "action": "TakeFriendVideoPosition",
+                        "friendGoogleUserId": join_friend_video.friendGoogleUserId,
+                        "videoUrl": video_url_at_position,
+                        "positionInSeconds": position_in_seconds,
+                        "playbackState": current_video.playbackState,
+                        "serverTimeStampInMilliseconds": now,
+                        "videoData": current_video
+                    }).to_string()
+                },
+                None => json!({"action": "ERROR", "message": "Friend is not watching a video"}).to_string()
+            }
+        },
+        Err(_err) => {
+            println!("Invalid join friend video.");
+            "{}".to_owned()
+        }
+    }
+}
+
+// Takes the user off "friends on youtube now" once their video is over or they navigated away
+fn clear_current_video(registry: &ConnectionRegistry, client_conn_id: u32, reason: &str) {
+    let had_current_video = registry.update(client_conn_id, |conn_metadata| conn_metadata.currentVideo.take().is_some());
+
+    if had_current_video == Some(true) {
+        registry.broadcast_to_online_friends(client_conn_id, |conn_metadata| {
+            json!({
+                "action": "TakeFriendVideoCleared",
+                "googleUserId": conn_metadata.googleUserId,
+                "reason": reason
+            }).to_string()
+        });
+    }
+}
+
+fn handle_start_watch_party(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
+    let start_watch_party_maybe: Result<StartWatchPartyMessage, Error> = serde_json::from_str(json);
+
+    match start_watch_party_maybe {
+        Ok(_start_watch_party) => {
+            let client_conn_id = ws_client.connection_id();
+            let connected_clients = registry.read();
+
+            let conn_metadata = match connected_clients.get(client_conn_id) {
+                Some(conn_metadata) => conn_metadata,
+                None => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+            let current_video = match &conn_metadata.currentVideo {
+                Some(current_video) => current_video.clone(),
+                None => return json!({"action": "ERROR", "message": "No video is being watched"}).to_string()
+            };
+
+            // A socket is in at most one party at a time
+            leave_watch_party(&connected_clients, watch_parties, client_conn_id);
+
+            let mut watch_parties = watch_parties.lock().unwrap();
+            let host = WatchPartyMember {
+                socketId: client_conn_id,
+                googleUserId: conn_metadata.googleUserId.to_string()
+            };
+            let party = watch_parties.start(host, current_video, Utc::now().timestamp_millis());
+
+            json!({
+                "action": "TakeWatchParty",
+                "party": party
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid start watch party.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_watch_party_invite(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
+    let invite_maybe: Result<InviteToWatchPartyMessage, Error> = serde_json::from_str(json);
+
+    match invite_maybe {
+        Ok(invite) => {
+            let client_conn_id = ws_client.connection_id();
+            let connected_clients = registry.read();
+            let mut watch_parties = watch_parties.lock().unwrap();
+
+            let party = match watch_parties.get_mut(invite.partyId) {
+                Some(party) if party.host.socketId == client_conn_id => party,
+                _ => return json!({"action": "ERROR", "message": "Only the host can invite to a watch party"}).to_string()
+            };
+
+            let host_user = match storage.users.find_user(&party.host.googleUserId).expect("Error loading watch party host") {
+                Some(host_user) => host_user,
+                None => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+
+            // Nobody on either side of an exclusion or block gets invited
+            let friends_of_host: Vec<String> = storage.friendships.friends_of(&party.host.googleUserId)
+                .expect("Error loading watch party invitees")
+                .into_iter()
+                .filter(|(row, row_back, _)| row.allows_interaction() && row_back.as_ref().is_some_and(UserFriend::allows_interaction))
+                .map(|(row, _, _)| row.friend_google_uid)
+                .filter(|friend_google_user_id| invite.friendGoogleUserIds.contains(friend_google_user_id))
+                .collect();
+
+            let broadcast_data = json!({
+                "action": "TakeWatchPartyInvite",
+                "partyId": party.partyId,
+                "host": {
+                    "googleUserId": party.host.googleUserId,
+                    "fullName": host_user.full_name,
+                    "imageUrl": host_user.image_url
+                },
+                "videoData": party.videoData
+            });
+
+            let mut invited_online: Vec<&String> = vec![];
+            for friend_google_user_id in friends_of_host.iter() {
+                let friend_connections = connected_clients.connections_of(friend_google_user_id);
+
+                let mut is_online = false;
+                for meta in friend_connections {
+                    is_online = true;
+                    let _ = meta.socket.send(broadcast_data.to_string());
+                }
+
+                if is_online {
+                    if !party.is_invited(friend_google_user_id) {
+                        party.invitedGoogleUserIds.push(friend_google_user_id.to_string());
+                    }
+                    invited_online.push(friend_google_user_id);
+                }
+            }
+
+            json!({
+                "action": "TakeWatchPartyInvitesSent",
+                "partyId": party.partyId,
+                "friendGoogleUserIds": invited_online
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid watch party invite.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_join_watch_party(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
+    let join_maybe: Result<WatchPartyMembershipMessage, Error> = serde_json::from_str(json);
+
+    match join_maybe {
+        Ok(join) => {
+            let client_conn_id = ws_client.connection_id();
+            let connected_clients = registry.read();
+
+            let google_user_id = match connected_clients.get(client_conn_id) {
+                Some(conn_metadata) => conn_metadata.googleUserId.to_string(),
+                None => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+
+            let can_join = watch_parties.lock().unwrap()
+                .get(join.partyId)
+                .is_some_and(|party| party.is_member(client_conn_id) || party.is_invited(&google_user_id));
+
+            if !can_join {
+                return json!({"action": "ERROR", "message": "Not invited to this watch party"}).to_string();
+            }
+
+            if watch_parties.lock().unwrap().party_of(client_conn_id) != Some(join.partyId) {
+                leave_watch_party(&connected_clients, watch_parties, client_conn_id);
+            }
+
+            let mut watch_parties = watch_parties.lock().unwrap();
+            let party = match watch_parties.get_mut(join.partyId) {
+                Some(party) => party,
+                None => return json!({"action": "ERROR", "message": "Watch party has ended"}).to_string()
+            };
+
+            if !party.is_member(client_conn_id) {
+                let broadcast_data = json!({
+                    "action": "TakeWatchPartyMemberJoined",
+                    "partyId": party.partyId,
+                    "googleUserId": google_user_id
+                });
+                connected_clients.send_to_sockets(&party.member_sockets(), &broadcast_data.to_string());
+
+                party.members.push(WatchPartyMember {
+                    socketId: client_conn_id,
+                    googleUserId: google_user_id
+                });
+            }
+
+            let now = Utc::now().timestamp_millis();
+            json!({
+                "action": "TakeWatchParty",
+                "party": party,
+                "estimatedPositionInSeconds": party.current_position(now),
+                "serverTimeStampInMilliseconds": now
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid join watch party.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_leave_watch_party(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
+    let leave_maybe: Result<WatchPartyMembershipMessage, Error> = serde_json::from_str(json);
+
+    match leave_maybe {
+        Ok(_leave) => {
+            let connected_clients = registry.read();
+            leave_watch_party(&connected_clients, watch_parties, ws_client.connection_id());
+        },
+        Err(_err) => {
+            println!("Invalid leave watch party.");
+        }
+    };
+
+    "{}".to_owned()
+}
+
+// Only the host drives playback. The server stamps each event so members can correct for latency.
+fn handle_watch_party_playback(json: &str, _storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, watch_parties: &Mutex<WatchParties>) -> String {
+    let playback_maybe: Result<WatchPartyPlaybackMessage, Error> = serde_json::from_str(json);
+
+    match playback_maybe {
+        Ok(playback) => {
+            let client_conn_id = ws_client.connection_id();
+            let connected_clients = registry.read();
+            let mut watch_parties = watch_parties.lock().unwrap();
+
+            let party = match watch_parties.get_mut(playback.partyId) {
+                Some(party) if party.host.socketId == client_conn_id => party,
+                _ => return json!({"action": "ERROR", "message": "Only the host controls watch party playback"}).to_string()
+            };
+
+            let now = Utc::now().timestamp_millis();
+            party.set_playback(playback.state, playback.positionInSeconds, now);
+
+            let broadcast_data = json!({
+                "action": "TakeWatchPartyPlayback",
+                "partyId": party.partyId,
+                "state": party.playbackState,
+                "positionInSeconds": party.positionInSeconds,
+                "serverTimeStampInMilliseconds": now
+            });
+            let member_sockets: Vec<u32> = party.member_sockets().into_iter()
+                .filter(|socket_id| *socket_id != client_conn_id)
+                .collect();
+            connected_clients.send_to_sockets(&member_sockets, &broadcast_data.to_string());
+        },
+        Err(_err) => {
+            println!("Invalid watch party playback.");
+        }
+    };
+
+    "{}".to_owned()
+}
+
+// Called on explicit leave, going offline and disconnect, handing the party off if the host left
+fn leave_watch_party(connected_clients: &Connections, watch_parties: &Mutex<WatchParties>, client_conn_id: u32) {
+    let mut watch_parties = watch_parties.lock().unwrap();
+
+    let party_id = match watch_parties.party_of(client_conn_id) {
+        Some(party_id) => party_id,
+        None => return
+    };
+    let departure = watch_parties.leave(party_id, client_conn_id);
+
+    if let Some(party) = watch_parties.get(party_id) {
+        let departed_google_user_id = connected_clients.get(client_conn_id)
+            .map(|conn_metadata| conn_metadata.googleUserId.to_string());
+
+        let broadcast_data = json!({
+            "action": "TakeWatchPartyMemberLeft",
+            "partyId": party_id,
+            "googleUserId": departed_google_user_id
+        });
+        connected_clients.send_to_sockets(&party.member_sockets(), &broadcast_data.to_string());
+
+        if let WatchPartyDeparture::HostChanged(new_host) = departure {
+            let broadcast_data = json!({
+                "action": "TakeWatchPartyHostChanged",
+                "partyId": party_id,
+                "hostGoogleUserId": new_host.googleUserId,
+                "state": party.playbackState,
+                "positionInSeconds": party.current_position(Utc::now().timestamp_millis()),
+                "serverTimeStampInMilliseconds": Utc::now().timestamp_millis()
+            });
+            connected_clients.send_to_sockets(&party.member_sockets(), &broadcast_data.to_string());
+        }
+    }
+}
+
+
+// Lifting an exclusion or block takes effect when either side identifies again, like new friendships do
+#[allow(unused_variables)]
+fn handle_friend_exclusion(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let friend_exclusion_maybe: Result<FriendExclusionMessage, Error> = serde_json::from_str(json);
+
+    match friend_exclusion_maybe {
+        Ok(friend_exclusion) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let exclusion_update = storage.friendships
+                .set_excluded(&google_user_id, &friend_exclusion.friendGoogleUserId, friend_exclusion.exclude);
+
+            if let Err(err) = exclusion_update {
+                println!("Error updating friend exclusion: {:?}", err);
+                return json!({"action": "ERROR", "message": "Could not update the friend exclusion"}).to_string();
+            }
+
+            if friend_exclusion.exclude {
+                registry.write().hide_from_each_other(&google_user_id, &friend_exclusion.friendGoogleUserId);
+            }
+        },
+        Err(err_msg) => {
+            println!("Invalid friend exclusion.");
+        }
+    }
+
+    "{}".to_owned()
+}
+
+fn handle_friend_block(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let friend_block_maybe: Result<FriendBlockMessage, Error> = serde_json::from_str(json);
+
+    match friend_block_maybe {
+        Ok(friend_block) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let block_update = storage.friendships
+                .set_blocked(&google_user_id, &friend_block.friendGoogleUserId, friend_block.block, Utc::now().naive_utc());
+
+            if let Err(err) = block_update {
+                println!("Error updating friend block: {:?}", err);
+                return json!({"action": "ERROR", "message": "Could not update the friend block"}).to_string();
+            }
+
+            if friend_block.block {
+                registry.write().hide_from_each_other(&google_user_id, &friend_block.friendGoogleUserId);
+            }
+        },
+        Err(_err) => {
+            println!("Invalid friend block.");
+        }
+    }
+
+    "{}".to_owned()
+}
+
+// Both sides must have the friendship, and neither may have excluded or blocked the other.
+// The error is the reply for when the friendship could not be loaded.
+fn can_interact(google_user_id: &str, friend_google_user_id: &str, friendships: &dyn FriendshipRepository) -> Result<bool, String> {
+    let friendship_rows = friendships.find_friendship(google_user_id, friend_google_user_id)
+        .and_then(|user_friend_row| Ok((user_friend_row, friendships.find_friendship(friend_google_user_id, google_user_id)?)));
+
+    match friendship_rows {
+        Ok((Some(user_friend_row), Some(friend_row_back))) => Ok(user_friend_row.allows_interaction() && friend_row_back.allows_interaction()),
+        Ok(_) => Ok(false),
+        Err(err) => {
+            println!("Error loading friendship: {:?}", err);
+            Err(json!({"action": "ERROR", "message": "Could not load the friendship"}).to_string())
+        }
+    }
+}
+
+fn chat_message_json(message: &ChatMessage) -> JsonValue {
+    json!({
+        "messageId": message.id,
+        "senderGoogleUserId": message.sender_google_uid,
+        "recipientGoogleUserId": message.recipient_google_uid,
+        "body": message.body,
+        "createdAtInMilliseconds": to_milliseconds(&message.created_at),
+        "deliveredAtInMilliseconds": message.delivered_at.as_ref().map(to_milliseconds),
+        "readAtInMilliseconds": message.read_at.as_ref().map(to_milliseconds)
+    })
+}
+
+fn handle_send_chat_message(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let chat_message_maybe: Result<SendChatMessage, Error> = serde_json::from_str(json);
+
+    match chat_message_maybe {
+        Ok(chat_message) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let message_body = chat_message.body.trim();
+            if message_body.is_empty() || message_body.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+                return json!({
+                    "action": "ERROR",
+                    "message": format!("Chat messages must be between 1 and {} characters", MAX_CHAT_MESSAGE_LENGTH)
+                }).to_string();
+            }
+
+            match can_interact(&google_user_id, &chat_message.friendGoogleUserId, storage.friendships) {
+                Ok(true) => {},
+                Ok(false) => return json!({"action": "ERROR", "message": "Cannot chat with this user"}).to_string(),
+                Err(reply) => return reply
+            }
+
+            let now = Utc::now().naive_utc();
+            let new_message = NewMessage {
+                sender_google_uid: &google_user_id,
+                recipient_google_uid: &chat_message.friendGoogleUserId,
+                body: message_body,
+                created_at: now,
+            };
+
+            let mut message_db_record = storage.chat.create_message(&new_message)
+                .expect("Error saving new chat message");
+
+            let broadcast_data = json!({
+                "action": "TakeChatMessage",
+                "message": chat_message_json(&message_db_record)
+            });
+
+            let was_delivered = {
+                let connected_clients = registry.read();
+                notify_user(&connected_clients, &chat_message.friendGoogleUserId, &broadcast_data, storage.inbox)
+            };
+
+            if was_delivered {
+                message_db_record = storage.chat.mark_message_delivered(message_db_record.id, now)
+                    .expect("Error marking chat message delivered");
+            }
+
+            json!({
+                "action": "TakeChatMessageSent",
+                "clientMessageId": chat_message.clientMessageId,
+                "message": chat_message_json(&message_db_record)
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid send chat message.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_chat_messages_read(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let messages_read_maybe: Result<MarkChatMessagesReadMessage, Error> = serde_json::from_str(json);
+
+    match messages_read_maybe {
+        Ok(messages_read) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let now = Utc::now().naive_utc();
+            let read_messages = storage.chat
+                .mark_messages_read(&google_user_id, &messages_read.friendGoogleUserId, messages_read.upToMessageId, now)
+                .expect("Error marking chat messages read");
+
+            if !read_messages.is_empty() {
+                let receipt_data = json!({
+                    "action": "TakeChatMessageReceipt",
+                    "status": "read",
+                    "friendGoogleUserId": google_user_id,
+                    "messageIds": read_messages.iter().map(|message| message.id).collect::<Vec<i64>>(),
+                    "timeStampInMilliseconds": to_milliseconds(&now)
+                });
+
+                let connected_clients = registry.read();
+                connected_clients.send_to_user(&messages_read.friendGoogleUserId, &receipt_data.to_string());
+            }
+        },
+        Err(_err) => {
+            println!("Invalid mark chat messages read.");
+        }
+    };
+
+    "{}".to_owned()
+}
+
+fn handle_react_to_friend_video(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let reaction_maybe: Result<ReactToFriendVideoMessage, Error> = serde_json::from_str(json);
+
+    match reaction_maybe {
+        Ok(reaction) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let reaction_emoji = reaction.emoji.as_deref().map(str::trim).filter(|e| !e.is_empty());
+            let reaction_comment = reaction.comment.as_deref().map(str::trim).filter(|c| !c.is_empty());
+
+            let is_valid_reaction = (reaction_emoji.is_some() || reaction_comment.is_some())
+                && reaction_emoji.is_none_or(|e| e.chars().count() <= MAX_REACTION_EMOJI_LENGTH)
+                && reaction_comment.is_none_or(|c| c.chars().count() <= MAX_REACTION_COMMENT_LENGTH);
+
+            if !is_valid_reaction {
+                return json!({
+                    "action": "ERROR",
+                    "message": format!("A reaction needs an emoji or a comment of at most {} characters", MAX_REACTION_COMMENT_LENGTH)
+                }).to_string();
+            }
+
+            match can_interact(&google_user_id, &reaction.friendGoogleUserId, storage.friendships) {
+                Ok(true) => {},
+                Ok(false) => return json!({"action": "ERROR", "message": "Cannot react to this user's video"}).to_string(),
+                Err(reply) => return reply
+            }
+
+            let friend_current_video = {
+                let connected_clients = registry.read();
+                connected_clients.connections_of(&reaction.friendGoogleUserId)
+                    .filter_map(|meta| meta.currentVideo.clone())
+                    .max_by_key(|current_video| current_video.positionTimeStampInMilliseconds)
+            };
+
+            let current_video = match friend_current_video {
+                Some(current_video) => current_video,
+                None => return json!({"action": "ERROR", "message": "Friend is not watching a video"}).to_string()
+            };
+
+            let video_row = match storage.videos.find_video(&current_video.platform, &current_video.externalId)
+                .expect("Error loading reacted video")
+            {
+                Some(video_row) => video_row,
+                None => return json!({"action": "ERROR", "message": "Friend is not watching a video"}).to_string()
+            };
+
+            let reactor = storage.users.find_user(&google_user_id)
+                .expect("Error loading reacting user");
+
+            let new_reaction = NewVideoReaction {
+                video_id: video_row.id,
+                watcher_google_uid: &reaction.friendGoogleUserId,
+                reactor_google_uid: &google_user_id,
+                emoji: reaction_emoji,
+                comment: reaction_comment,
+                created_at: Utc::now().naive_utc(),
+            };
+
+            let reaction_db_record = storage.reactions.create_reaction(&new_reaction)
+                .expect("Error saving video reaction");
+
+            let reaction_data = video_reaction_json(&reaction_db_record, reactor.as_ref());
+
+            let broadcast_data = json!({
+                "action": "TakeVideoReaction",
+                "videoData": current_video,
+                "reaction": reaction_data
+            });
+            {
+                let connected_clients = registry.read();
+                notify_user(&connected_clients, &reaction.friendGoogleUserId, &broadcast_data, storage.inbox);
+            }
+
+            json!({
+                "action": "TakeVideoReactionSent",
+                "friendGoogleUserId": reaction.friendGoogleUserId,
+                "reaction": reaction_data
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid video reaction.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn video_reaction_json(reaction: &VideoReaction, reactor: Option<&Usermaster>) -> JsonValue {
+    json!({
+        "reactionId": reaction.id,
+        "googleUserId": reaction.reactor_google_uid,
+        "fullName": reactor.map(|user| user.full_name.to_owned()),
+        "imageUrl": reactor.map(|user| user.image_url.to_owned()),
+        "emoji": reaction.emoji,
+        "comment": reaction.comment,
+        "createdAtInMilliseconds": to_milliseconds(&reaction.created_at)
+    })
+}
+
+fn handle_get_watch_history(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let watch_history_maybe: Result<GetWatchHistoryMessage, Error> = serde_json::from_str(json);
+
+    match watch_history_maybe {
+        Ok(watch_history) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            // Friends only see each other's history while neither side has excluded or blocked the other
+            let history_google_user_id = watch_history.friendGoogleUserId.unwrap_or_else(|| google_user_id.to_owned());
+            if history_google_user_id != google_user_id {
+                match can_interact(&google_user_id, &history_google_user_id, storage.friendships) {
+                    Ok(true) => {},
+                    Ok(false) => return json!({"action": "ERROR", "message": "Cannot see this user's watch history"}).to_string(),
+                    Err(reply) => return reply
+                }
+            }
+
+            let page_size = watch_history.limit.unwrap_or(DEFAULT_WATCH_HISTORY_PAGE_SIZE).clamp(1, 200);
+            let page_offset = watch_history.offset.unwrap_or(0).max(0);
+
+            let existing_user = match storage.users.find_user(&history_google_user_id).expect("Error loading user") {
+                Some(existing_user) => existing_user,
+                None => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+
+            let watched_videos = storage.videos.watch_history(existing_user.id, page_size, page_offset)
+                .expect("Error loading watch history");
+
+            let watched_video_ids: Vec<i64> = watched_videos.iter().map(|(_, video)| video.id).collect();
+
+            let reactions = storage.reactions.reactions_to(&history_google_user_id, &watched_video_ids)
+                .expect("Error loading video reactions");
+
+            let history: Vec<JsonValue> = watched_videos.iter()
+                .map(|(user_video, video)| json!({
+                    "videoData": stored_video_json(video),
+                    "watchedAtInMilliseconds": to_milliseconds(&user_video.created_at),
+                    "reactions": reactions.iter()
+                        .filter(|(reaction, _)| reaction.video_id == video.id)
+                        .map(|(reaction, reactor)| video_reaction_json(reaction, Some(reactor)))
+                        .collect::<Vec<JsonValue>>()
+                }))
+                .collect();
+
+            json!({
+                "action": "TakeWatchHistory",
+                "offset": page_offset,
+                "history": history
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get watch history.");
+            "{}".to_owned()
+        }
+    }
+}
+
+// What offline friends were last up to, for "last seen 2h ago watching ..."
+fn fill_last_seen(offline_friends: Vec<&mut UserFriendEntity>, storage: &Storage) {
+    let last_video_ids: Vec<i64> = offline_friends.iter()
+        .filter_map(|friend| friend.friend.last_video_id)
+        .collect();
+    let last_videos: HashMap<i64, Video> = storage.videos.find_videos(&last_video_ids)
+        .expect("Error loading last watched videos")
+        .into_iter()
+        .map(|video| (video.id, video))
+        .collect();
+
+    for friend in offline_friends {
+        friend.last_seen_at_in_milliseconds = friend.friend.last_seen_at.as_ref().map(to_milliseconds);
+        friend.last_video = friend.friend.last_video_id
+            .and_then(|last_video_id| last_videos.get(&last_video_id))
+            .map(stored_video_json);
+    }
+}
+
+// The subset of WsConnectedClientCurrentVideo that is kept in the videos table
+fn stored_video_json(video: &Video) -> JsonValue {
+    json!({
+        "platform": video.platform,
+        "externalId": video.external_id,
+        "videoUrl": video.video_url,
+        "title": video.video_title,
+        "thumbnail_url": video.thumbnail_default_url,
+        "channelTitle": video.channel_title,
+        "durationInSeconds": video.duration_seconds
+    })
+}
+
+// Sends the event live, or keeps it in the user's inbox when none of their sockets are connected
+fn notify_user(connected_clients: &Connections, google_user_id: &str, event: &JsonValue, inbox: &dyn InboxRepository) -> bool {
+    let event_payload = event.to_string();
+    if connected_clients.send_to_user(google_user_id, &event_payload) {
+        return true;
+    }
+
+    let new_notification = NewNotification {
+        recipient_google_uid: google_user_id,
+        action: event["action"].as_str().unwrap_or(""),
+        payload: &event_payload,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    inbox.store_notification(&new_notification)
+        .expect("Error saving notification");
+
+    false
+}
+
+fn take_pending_notifications(google_user_id: &str, inbox: &dyn InboxRepository) -> Vec<JsonValue> {
+    let pending_notifications = inbox.take_pending_notifications(google_user_id, MAX_PENDING_NOTIFICATIONS, Utc::now().naive_utc())
+        .expect("Error loading notifications");
+
+    pending_notifications.iter()
+        .map(|notification| json!({
+            "notificationId": notification.id,
+            "createdAtInMilliseconds": to_milliseconds(&notification.created_at),
+            "event": serde_json::from_str::<JsonValue>(&notification.payload).unwrap_or(JsonValue::Null)
+        }))
+        .collect()
+}
+
+// Chat messages sent while the user was away reach them through the inbox, so tell the senders
+fn deliver_pending_chat_messages(connected_clients: &Connections, google_user_id: &str, inbox: &dyn InboxRepository) {
+    let now = Utc::now().naive_utc();
+    let delivered_messages = inbox.deliver_chat_messages(google_user_id, now)
+        .expect("Error marking chat messages delivered");
+
+    let mut delivered_by_sender: HashMap<&str, Vec<i64>> = HashMap::new();
+    for message in delivered_messages.iter() {
+        delivered_by_sender.entry(message.sender_google_uid.as_str()).or_default().push(message.id);
+    }
+
+    for (sender_google_user_id, message_ids) in delivered_by_sender {
+        let receipt_data = json!({
+            "action": "TakeChatMessageReceipt",
+            "status": "delivered",
+            "friendGoogleUserId": google_user_id,
+            "messageIds": message_ids,
+            "timeStampInMilliseconds": to_milliseconds(&now)
+        });
+        connected_clients.send_to_user(sender_google_user_id, &receipt_data.to_string());
+    }
+}
+
+fn handle_notifications_read(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let notifications_read_maybe: Result<MarkNotificationsReadMessage, Error> = serde_json::from_str(json);
+
+    match notifications_read_maybe {
+        Ok(notifications_read) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let now = Utc::now().naive_utc();
+            let marked_count = storage.inbox
+                .mark_notifications_read(&google_user_id, notifications_read.notificationIds.as_deref(), now)
+                .expect("Error marking notifications read");
+
+            json!({
+                "action": "TakeNotificationsRead",
+                "count": marked_count
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid mark notifications read.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_share_video(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, metadata: &dyn MetadataProvider) -> String {
+    let share_video_maybe: Result<ShareVideoMessage, Error> = serde_json::from_str(json);
+
+    match share_video_maybe {
+        Ok(share_video) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let share_note = share_video.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+            if share_note.is_some_and(|n| n.chars().count() > MAX_SHARE_NOTE_LENGTH) {
+                return json!({
+                    "action": "ERROR",
+                    "message": format!("Share notes must be at most {} characters", MAX_SHARE_NOTE_LENGTH)
+                }).to_string();
+            }
+
+            let sender = match storage.users.find_user(&google_user_id).expect("Error loading sharing user") {
+                Some(sender) => sender,
+                None => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+
+            let shared_video_url = match parse_video_url(&share_video.videoUrl) {
+                Some(shared_video_url) => shared_video_url,
+                None => return json!({"action": "ERROR", "message": "Invalid video url"}).to_string()
+            };
+
+            let video_metadata = match metadata.video_metadata(&shared_video_url) {
+                Ok(video_metadata) => video_metadata,
+                Err(err_msg) => return json!({"action": "ERROR", "message": err_msg}).to_string()
+            };
+
+            let video_db_record = persist_video(&shared_video_url, &video_metadata, storage);
+            let now = Utc::now();
+            let video_data = WsConnectedClientCurrentVideo::from(&shared_video_url, &video_metadata, now.timestamp_millis());
+
+            let mut shared_with: Vec<&String> = vec![];
+            let mut skipped: Vec<&String> = vec![];
+
+            for friend_google_user_id in share_video.friendGoogleUserIds.iter() {
+                match can_interact(&google_user_id, friend_google_user_id, storage.friendships) {
+                    Ok(true) => {},
+                    Ok(false) => {
+                        skipped.push(friend_google_user_id);
+                        continue;
+                    },
+                    Err(reply) => return reply
+                }
+
+                let new_video_share = NewVideoShare {
+                    video_id: video_db_record.id,
+                    sender_google_uid: &google_user_id,
+                    recipient_google_uid: friend_google_user_id,
+                    note: share_note,
+                    created_at: now.naive_utc(),
+                };
+
+                let video_share_db_record = storage.shares.create_share(&new_video_share)
+                    .expect("Error saving video share");
+
+                let broadcast_data = json!({
+                    "action": "TakeSharedVideo",
+                    "shareId": video_share_db_record.id,
+                    "sharedBy": {
+                        "googleUserId": google_user_id,
+                        "fullName": sender.full_name,
+                        "imageUrl": sender.image_url
+                    },
+                    "videoData": video_data,
+                    "note": video_share_db_record.note,
+                    "createdAtInMilliseconds": to_milliseconds(&video_share_db_record.created_at)
+                });
+
+                let connected_clients = registry.read();
+                notify_user(&connected_clients, friend_google_user_id, &broadcast_data, storage.inbox);
+                shared_with.push(friend_google_user_id);
+            }
+
+            json!({
+                "action": "TakeVideoShared",
+                "videoData": video_data,
+                "friendGoogleUserIds": shared_with,
+                "skippedGoogleUserIds": skipped
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid share video.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_get_friend_suggestions(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let friend_suggestions_maybe: Result<GetFriendSuggestionsMessage, Error> = serde_json::from_str(json);
+
+    match friend_suggestions_maybe {
+        Ok(friend_suggestions) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let suggestions_limit = friend_suggestions.limit.unwrap_or(DEFAULT_FRIEND_SUGGESTIONS_LIMIT).clamp(1, 100);
+
+            let suggestions = storage.friendships
+                .friend_suggestions(&google_user_id, suggestions_limit, FRIEND_SUGGESTIONS_RECENT_VIDEOS)
+                .expect("Error loading friend suggestions");
+
+            let suggestions: Vec<JsonValue> = suggestions.iter()
+                .map(|suggestion| json!({
+                    "googleUserId": suggestion.uid,
+                    "fullName": suggestion.full_name,
+                    "imageUrl": suggestion.image_url,
+                    "mutualFriendCount": suggestion.mutual_friend_count,
+                    "sharedVideoCount": suggestion.shared_video_count
+                }))
+                .collect();
+
+            json!({
+                "action": "TakeFriendSuggestions",
+                "suggestions": suggestions
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get friend suggestions.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn public_profile_json(profile: &PublicProfile) -> JsonValue {
+    json!({
+        "googleUserId": profile.uid,
+        "fullName": profile.full_name,
+        "imageUrl": profile.image_url
+    })
+}
+
+fn handle_search_users(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let search_users_maybe: Result<SearchUsersMessage, Error> = serde_json::from_str(json);
+
+    match search_users_maybe {
+        Ok(search_users) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let search_query = search_users.query.trim();
+            if search_query.is_empty() || search_query.chars().count() > MAX_USER_SEARCH_QUERY_LENGTH {
+                return json!({
+                    "action": "ERROR",
+                    "message": format!("Search queries must be between 1 and {} characters", MAX_USER_SEARCH_QUERY_LENGTH)
+                }).to_string();
+            }
+            let search_limit = search_users.limit.unwrap_or(DEFAULT_USER_SEARCH_LIMIT).clamp(1, 100);
+
+            let users = storage.users.search_users(&google_user_id, search_query, search_limit)
+                .expect("Error searching users");
+
+            json!({
+                "action": "TakeUserSearchResults",
+                "query": search_users.query,
+                "users": users.iter().map(public_profile_json).collect::<Vec<JsonValue>>()
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid search users.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_get_profile(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let get_profile_maybe: Result<GetProfileMessage, Error> = serde_json::from_str(json);
+
+    match get_profile_maybe {
+        Ok(get_profile) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let profile_user = storage.users.find_user(&get_profile.profileGoogleUserId)
+                .expect("Error loading profile user");
+
+            let friendship_row = storage.friendships.find_friendship(&google_user_id, &get_profile.profileGoogleUserId)
+                .expect("Error loading friendship");
+            let friendship_row_back = storage.friendships.find_friendship(&get_profile.profileGoogleUserId, &google_user_id)
+                .expect("Error loading friendship");
+            let friendship_rows: Vec<UserFriend> = friendship_row.into_iter().chain(friendship_row_back).collect();
+
+            // Hidden and blocking users look exactly like users that do not exist
+            let is_blocked = friendship_rows.iter().any(|row| row.is_friend_blocked);
+            let profile_user = match profile_user {
+                Some(user) if !is_blocked && (user.is_discoverable || !friendship_rows.is_empty() || user.uid == google_user_id) => user,
+                _ => return json!({"action": "ERROR", "message": "Unknown user"}).to_string()
+            };
+
+            let is_friend = match can_interact(&google_user_id, &get_profile.profileGoogleUserId, storage.friendships) {
+                Ok(is_friend) => is_friend,
+                Err(reply) => return reply
+            };
+
+            let mutual_friends = storage.friendships.mutual_friends(&google_user_id, &get_profile.profileGoogleUserId)
+                .expect("Error loading mutual friends");
+
+            json!({
+                "action": "TakeProfile",
+                "googleUserId": profile_user.uid,
+                "fullName": profile_user.full_name,
+                "imageUrl": profile_user.image_url,
+                "memberSinceInMilliseconds": to_milliseconds(&profile_user.created_at),
+                "isFriend": is_friend,
+                "mutualFriends": mutual_friends.iter().map(public_profile_json).collect::<Vec<JsonValue>>()
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get profile.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_discoverability(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let discoverability_maybe: Result<DiscoverabilityMessage, Error> = serde_json::from_str(json);
+
+    match discoverability_maybe {
+        Ok(discoverability) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            storage.users.set_discoverable(&google_user_id, discoverability.isDiscoverable, Utc::now().naive_utc())
+                .expect("Error updating discoverability");
+        },
+        Err(_err) => {
+            println!("Invalid discoverability.");
+        }
+    }
+    "{}".to_owned()
+}
+
+fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| *INVITE_CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+fn handle_create_invite_code(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let create_invite_code_maybe: Result<CreateInviteCodeMessage, Error> = serde_json::from_str(json);
+
+    match create_invite_code_maybe {
+        Ok(create_invite_code) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let inviter = storage.users.find_user(&google_user_id)
+                .expect("Error loading inviter");
+
+            if inviter.is_none() {
+                return json!({"action": "ERROR", "message": "Unknown user"}).to_string();
+            }
+
+            // A multi-use code without maxUses can be redeemed until it expires
+            let invite_max_uses = if create_invite_code.multiUse {
+                match create_invite_code.maxUses {
+                    Some(uses) if uses < 1 => return json!({"action": "ERROR", "message": "maxUses must be at least 1"}).to_string(),
+                    uses => uses
+                }
+            } else {
+                Some(1)
+            };
+            let now = Utc::now().naive_utc();
+            let ttl_days = create_invite_code.expiresInDays.unwrap_or(DEFAULT_INVITE_CODE_TTL_DAYS).clamp(1, MAX_INVITE_CODE_TTL_DAYS);
+
+            let generated_code = generate_invite_code();
+            let new_invite_code = NewInviteCode {
+                code: &generated_code,
+                inviter_google_uid: &google_user_id,
+                max_uses: invite_max_uses,
+                expires_at: Some(now + chrono::Duration::days(ttl_days)),
+                created_at: now,
+            };
+
+            let invite_code_db_record = storage.invites.create_invite_code(&new_invite_code)
+                .expect("Error saving invite code");
+
+            json!({
+                "action": "TakeInviteCode",
+                "inviteCode": invite_code_db_record.code,
+                "maxUses": invite_code_db_record.max_uses,
+                "expiresAtInMilliseconds": invite_code_db_record.expires_at.as_ref().map(to_milliseconds)
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid create invite code.");
+            "{}".to_owned()
+        }
+    }
+}
+
+// Claims one use of the code and befriends the new user with whoever handed it out
+fn redeem_invite_code(invite_code: &str, invitee_google_user_id: &str, storage: &Storage, registry: &ConnectionRegistry) -> Option<JsonValue> {
+    let now = Utc::now().naive_utc();
+    let normalized_code = invite_code.trim().to_uppercase();
+
+    let claimed_code = match storage.invites.redeem_invite_code(&normalized_code, invitee_google_user_id, now) {
+        Ok(Some(claimed_code)) => claimed_code,
+        Ok(None) => {
+            println!("Invite code {} is unknown, used up or expired.", normalized_code);
+            return None;
+        },
+        Err(err) => {
+            println!("Error redeeming invite code {}: {:?}", normalized_code, err);
+            return None;
+        }
+    };
+
+    if let Err(err_msg) = make_friendship_rows(&claimed_code.inviter_google_uid, invitee_google_user_id, storage, registry) {
+        println!("Could not befriend invitee: {}", err_msg);
+        return None;
+    }
+
+    let inviter = storage.users.find_user(&claimed_code.inviter_google_uid)
+        .expect("Error loading inviter");
+
+    inviter.map(|inviter| json!({
+        "googleUserId": inviter.uid,
+        "fullName": inviter.full_name,
+        "imageUrl": inviter.image_url
+    }))
+}
+
+
+fn handle_get_friends_trending(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry, friends_trending_cache: &FriendsTrendingCache) -> String {
+    let friends_trending_maybe: Result<GetFriendsTrendingMessage, Error> = serde_json::from_str(json);
+
+    match friends_trending_maybe {
+        Ok(friends_trending) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let trending_limit = friends_trending.limit.unwrap_or(DEFAULT_FRIENDS_TRENDING_LIMIT).clamp(1, 100);
+            let cache_key = (google_user_id.to_owned(), friends_trending.window, trending_limit);
+
+            if let Some(cached_reply) = friends_trending_cache.lock().unwrap().get(&cache_key, Instant::now()) {
+                return cached_reply.to_owned();
+            }
+
+            let now = Utc::now().naive_utc();
+            let trending_videos = storage.discovery.friends_trending(&google_user_id, now - friends_trending.window.duration(), trending_limit)
+                .expect("Error loading trending videos");
+
+            let trending_video_ids: Vec<i64> = trending_videos.iter().map(|trending| trending.video_id).collect();
+            let viewer_google_user_ids: Vec<&str> = trending_videos.iter()
+                .flat_map(|trending| trending.viewer_google_uids.iter().map(String::as_str))
+                .collect();
+
+            let trending_video_records = storage.videos.find_videos(&trending_video_ids)
+                .expect("Error loading trending video records");
+
+            let viewers = storage.users.find_users(&viewer_google_user_ids)
+                .expect("Error loading trending viewers");
+
+            let trending: Vec<JsonValue> = trending_videos.iter()
+                .filter_map(|trending| {
+                    let video = trending_video_records.iter().find(|video| video.id == trending.video_id)?;
+                    let friends: Vec<JsonValue> = trending.viewer_google_uids.iter()
+                        .filter_map(|viewer_google_uid| viewers.iter().find(|viewer| viewer.uid == *viewer_google_uid))
+                        .map(|viewer| json!({
+                            "googleUserId": viewer.uid,
+                            "fullName": viewer.full_name,
+                            "imageUrl": viewer.image_url
+                        }))
+                        .collect();
+
+                    Some(json!({
+                        "videoData": stored_video_json(video),
+                        "viewerCount": trending.viewer_count,
+                        "lastWatchedAtInMilliseconds": to_milliseconds(&trending.last_watched_at),
+                        "friends": friends
+                    }))
+                })
+                .collect();
+
+            let reply = json!({
+                "action": "TakeFriendsTrending",
+                "window": friends_trending.window,
+                "videos": trending,
+                "generatedAtInMilliseconds": to_milliseconds(&now)
+            }).to_string();
+
+            friends_trending_cache.lock().unwrap()
+                .insert(cache_key, reply.to_owned(), friends_trending.window.cache_ttl(), Instant::now());
+            reply
+        },
+        Err(_err) => {
+            println!("Invalid get friends trending.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_get_recommendations(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let recommendations_maybe: Result<GetRecommendationsMessage, Error> = serde_json::from_str(json);
+
+    match recommendations_maybe {
+        Ok(get_recommendations) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            let page_size = get_recommendations.limit.unwrap_or(DEFAULT_RECOMMENDATIONS_PAGE_SIZE).clamp(1, 100);
+            let page_offset = get_recommendations.offset.unwrap_or(0).max(0);
+
+            let recommendations = load_recommendations(&google_user_id, page_size, page_offset, storage.discovery)
+                .expect("Error loading recommendations");
+
+            let recommended_video_ids: Vec<i64> = recommendations.iter().map(|recommendation| recommendation.video_id).collect();
+            let recommended_videos = storage.videos.find_videos(&recommended_video_ids)
+                .expect("Error loading recommended videos");
+
+            let recommended: Vec<JsonValue> = recommendations.iter()
+                .filter_map(|recommendation| {
+                    let video = recommended_videos.iter().find(|video| video.id == recommendation.video_id)?;
+                    Some(json!({
+                        "videoData": stored_video_json(video),
+                        "score": recommendation.score,
+                        "friendViewerCount": recommendation.friend_viewer_count
+                    }))
+                })
+                .collect();
+
+            json!({
+                "action": "TakeRecommendations",
+                "offset": page_offset,
+                "videos": recommended
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get recommendations.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_get_my_stats(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let my_stats_maybe: Result<GetMyStatsMessage, Error> = serde_json::from_str(json);
+
+    match my_stats_maybe {
+        Ok(my_stats) => {
+            let google_user_id = match identified_user(registry, ws_client) {
+                Ok(google_user_id) => google_user_id,
+                Err(reply) => return reply
+            };
+
+            // Real world offsets run from UTC-12 to UTC+14
+            let utc_offset_minutes = my_stats.utcOffsetMinutes.unwrap_or(0).clamp(-12 * 60, 14 * 60);
+
+            let user_stats = load_user_stats(&google_user_id, utc_offset_minutes, Utc::now().naive_utc(), storage.stats)
+                .expect("Error loading user stats");
+            let streaks = user_stats.streaks();
+
+            let bucket_json = |bucket: &tubepeek_server_rust::models::WatchCountBucket| json!({
+                "date": bucket.bucket.format("%Y-%m-%d").to_string(),
+                "videoCount": bucket.video_count
+            });
+
+            json!({
+                "action": "TakeMyStats",
+                "totalVideos": user_stats.totals.video_count,
+                "totalWatchTimeInSeconds": user_stats.totals.watch_seconds,
+                "videosWithUnknownDuration": user_stats.totals.unknown_duration_count,
+                "videosPerDay": user_stats.daily_counts().map(bucket_json).collect::<Vec<JsonValue>>(),
+                "videosPerWeek": user_stats.watch_weeks.iter().map(bucket_json).collect::<Vec<JsonValue>>(),
+                "topChannels": user_stats.top_channels.iter()
+                    .map(|channel| json!({
+                        "channelId": channel.group_id,
+                        "channelTitle": channel.group_title,
+                        "videoCount": channel.video_count
+                    }))
+                    .collect::<Vec<JsonValue>>(),
+                "topCategories": user_stats.top_categories.iter()
+                    .map(|category| json!({
+                        "categoryId": category.group_id,
+                        "categoryName": youtube_category_name(&category.group_id),
+                        "videoCount": category.video_count
+                    }))
+                    .collect::<Vec<JsonValue>>(),
+                "currentStreakInDays": streaks.current,
+                "longestStreakInDays": streaks.longest,
+                "closestTasteFriends": user_stats.taste_overlaps.iter()
+                    .map(|overlap| json!({
+                        "googleUserId": overlap.uid,
+                        "fullName": overlap.full_name,
+                        "imageUrl": overlap.image_url,
+                        "sharedVideoCount": overlap.shared_video_count,
+                        "similarity": overlap.similarity
+                    }))
+                    .collect::<Vec<JsonValue>>()
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get my stats.");
+            "{}".to_owned()
+        }
+    }
+}
+
+fn handle_get_leaderboard(json: &str, storage: &Storage, ws_client: &Sender, registry: &ConnectionRegistry) -> String {
+    let leaderboard_maybe: Result<GetLeaderboardMessage, Error> = serde_json::from_str(json);
+
+    match leaderboard_maybe {
+        Ok(get_leaderboard) => {
+            // The leaderboard is the same for everyone, but only signed in users get to see it
+            if let Err(reply) = identified_user(registry, ws_client) {
+                return reply;
+            }
+
+            let page_size = get_leaderboard.limit.unwrap_or(DEFAULT_LEADERBOARD_PAGE_SIZE).clamp(1, 200);
+            let page_offset = get_leaderboard.offset.unwrap_or(0).max(0);
+            let since = get_leaderboard.window.map(|window| Utc::now().naive_utc() - window.duration());
+
+            let leaderboard = storage.discovery.leaderboard(since, page_size, page_offset)
+                .expect("Error loading leaderboard");
+
+            let ranked_video_ids: Vec<i64> = leaderboard.iter().map(|popularity| popularity.video_id).collect();
+            let ranked_videos = storage.videos.find_videos(&ranked_video_ids)
+                .expect("Error loading leaderboard videos");
+
+            let ranked: Vec<JsonValue> = leaderboard.iter()
+                .enumerate()
+                .filter_map(|(position, popularity)| {
+                    let video = ranked_videos.iter().find(|video| video.id == popularity.video_id)?;
+                    Some(json!({
+                        "rank": page_offset + position as i64 + 1,
+                        "videoData": stored_video_json(video),
+                        "viewCount": popularity.view_count,
+                        "uniqueViewerCount": popularity.unique_viewer_count
+                    }))
+                })
+                .collect();
+
+            json!({
+                "action": "TakeLeaderboard",
+                "window": get_leaderboard.window,
+                "offset": page_offset,
+                "videos": ranked
+            }).to_string()
+        },
+        Err(_err) => {
+            println!("Invalid get leaderboard.");
+            "{}".to_owned()
+        }
+    }
+}
+
+
+fn main() {
+    // Maintenance commands run against the database and exit without starting the server
+    if let Some(command) = env::args().nth(1) {
+        match command.as_str() {
+            "rebuild-video-counters" => {
+                let connection = POOL.get().expect("Failed to get pooled connection");
+                let corrected = PgRepository::new(&connection).rebuild_video_counters().expect("Error rebuilding video counters");
+                println!("Rebuilt video counters, {} videos corrected.", corrected);
+            },
+            _ => println!("Unknown command {}, the only command is rebuild-video-counters.", command)
+        }
+        return;
+    }
+
+    println!("Tubepeek server up and running ...");
+    // dotenv().ok();
+
+    let server_ip = env::var("SELF_SERVER_HOST")
+        .expect("SELF_SERVER_HOST must be set");
+
+    let server_port = env::var("PORT")
+        .expect("PORT must be set");
+
+    env::var("YOUTUBE_API_KEY")
+        .expect("YOUTUBE_API_KEY must be set");
+
+
+    // STORAGE=memory runs the server without Postgres
+    let storage = match env::var("STORAGE").as_deref() {
+        Ok("memory") => StorageBackend::Memory(Arc::new(MemoryRepository::new())),
+        _ => StorageBackend::Postgres,
+    };
+
+    spawn_similarity_rebuilds(storage.clone());
+
+    let ws_mount_point = format!("{}:{}", server_ip, server_port);
+
+    let context = ServerContext::new(storage, Arc::new(PlatformMetadata), HeartbeatSettings::from_env());
 
-    if let Err(error) = listen(ws_mount_point, |out| WsServer { out: out }) {
+    if let Err(error) = listen(ws_mount_point, |out| WsServer::new(out, &context)) {
         println!("Failed to create WebSocket due to {:?}", error);
     };
 }
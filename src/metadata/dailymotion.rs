@@ -0,0 +1,51 @@
+use chrono::{TimeZone, Utc};
+
+use super::{VideoMetadata, VideoThumbnails};
+use crate::ws_dto::DailymotionVideoResponse;
+
+
+impl VideoMetadata {
+    pub fn from_dailymotion(video: &DailymotionVideoResponse) -> VideoMetadata {
+        VideoMetadata {
+            title: video.title.to_owned(),
+            channel_id: video.owner.to_owned(),
+            channel_title: video.owner_screenname.to_owned(),
+            published_at: video.created_time
+                .and_then(|created_time| Utc.timestamp_opt(created_time, 0).single())
+                .map(|created_time| created_time.naive_utc()),
+            category_id: video.channel.to_owned(),
+            thumbnails: VideoThumbnails {
+                default: video.thumbnail_120_url.to_owned(),
+                medium: video.thumbnail_360_url.to_owned(),
+                high: video.thumbnail_480_url.to_owned(),
+                maxres: video.thumbnail_720_url.to_owned(),
+            },
+            duration_seconds: video.duration,
+            is_live_broadcast: video.onair.unwrap_or(false),
+        }
+    }
+}
+
+
+pub fn fetch_video_metadata(dailymotion_video_id: &str) -> Result<VideoMetadata, &'static str> {
+    let dailymotion_query_url = format!(
+        "https://api.dailymotion.com/video/{}?fields={}",
+        dailymotion_video_id,
+        "title,owner,owner.screenname,created_time,channel,duration,onair,\
+         thumbnail_120_url,thumbnail_360_url,thumbnail_480_url,thumbnail_720_url"
+    );
+
+    let response = match reqwest::blocking::get(dailymotion_query_url.as_str()) {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid dailymotion response")
+    };
+
+    if !response.status().is_success() {
+        return Err("Dailymotion video not found");
+    }
+
+    match response.json::<DailymotionVideoResponse>() {
+        Ok(video) => Ok(VideoMetadata::from_dailymotion(&video)),
+        Err(_err) => Err("Invalid dailymotion json response format")
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+
+use crate::platform::{Platform, VideoUrl};
+
+pub mod youtube;
+pub mod vimeo;
+pub mod dailymotion;
+pub mod twitch;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoThumbnails {
+    pub default: String,
+    pub medium: Option<String>,
+    pub high: Option<String>,
+    pub maxres: Option<String>,
+}
+
+// What every platform's metadata provider resolves a video to
+#[derive(Debug)]
+pub struct VideoMetadata {
+    pub title: String,
+    pub channel_id: String,
+    pub channel_title: String,
+    pub published_at: Option<NaiveDateTime>,
+    pub category_id: Option<String>,
+    pub thumbnails: VideoThumbnails,
+    pub duration_seconds: Option<i64>,
+    pub is_live_broadcast: bool,
+}
+
+#[derive(Debug)]
+pub struct PlaylistMetadata {
+    pub title: String,
+    pub channel_id: String,
+    pub channel_title: String,
+    pub item_count: Option<i64>,
+    pub thumbnail_url: String,
+}
+
+pub fn fetch_video_metadata(video_url: &VideoUrl) -> Result<VideoMetadata, &'static str> {
+    let external_id = video_url.external_id.as_str();
+
+    match video_url.platform {
+        Platform::YouTube => youtube::fetch_video_metadata(external_id),
+        Platform::Vimeo => vimeo::fetch_video_metadata(external_id),
+        Platform::Dailymotion => dailymotion::fetch_video_metadata(external_id),
+        Platform::TwitchVod => twitch::fetch_video_metadata(external_id),
+    }
+}
+
+// Of the supported sites only YouTube links carry a playlist
+pub fn fetch_playlist_metadata(platform: Platform, playlist_id: &str) -> Result<PlaylistMetadata, &'static str> {
+    match platform {
+        Platform::YouTube => youtube::fetch_playlist_metadata(playlist_id),
+        _ => Err("Playlists are only supported on YouTube"),
+    }
+}
+
+// One based, like the index parameter of playlist urls
+pub fn fetch_playlist_position(platform: Platform, playlist_id: &str, external_id: &str) -> Result<u32, &'static str> {
+    match platform {
+        Platform::YouTube => youtube::fetch_playlist_position(playlist_id, external_id),
+        _ => Err("Playlists are only supported on YouTube"),
+    }
+}
+
+// Where the handlers get video and playlist details from, tests hand the server fixtures instead
+pub trait MetadataProvider: Send + Sync {
+    fn video_metadata(&self, video_url: &VideoUrl) -> Result<VideoMetadata, &'static str>;
+    fn playlist_metadata(&self, platform: Platform, playlist_id: &str) -> Result<PlaylistMetadata, &'static str>;
+    fn playlist_position(&self, platform: Platform, playlist_id: &str, external_id: &str) -> Result<u32, &'static str>;
+}
+
+// Asks each platform's own API
+pub struct PlatformMetadata;
+
+impl MetadataProvider for PlatformMetadata {
+    fn video_metadata(&self, video_url: &VideoUrl) -> Result<VideoMetadata, &'static str> {
+        fetch_video_metadata(video_url)
+    }
+
+    fn playlist_metadata(&self, platform: Platform, playlist_id: &str) -> Result<PlaylistMetadata, &'static str> {
+        fetch_playlist_metadata(platform, playlist_id)
+    }
+
+    fn playlist_position(&self, platform: Platform, playlist_id: &str, external_id: &str) -> Result<u32, &'static str> {
+        fetch_playlist_position(platform, playlist_id, external_id)
+    }
+}
@@ -0,0 +1,69 @@
+use std::env;
+use chrono::DateTime;
+
+use super::{VideoMetadata, VideoThumbnails};
+use crate::ws_dto::{TwitchVideoResponse, TwitchVideoResponseItem};
+use crate::utils::parse_timestamp_offset;
+
+
+impl VideoMetadata {
+    pub fn from_twitch(video: &TwitchVideoResponseItem) -> VideoMetadata {
+        // Twitch hands out a template url with %{width}x%{height} placeholders
+        let thumbnail_url = |width: u32, height: u32| {
+            video.thumbnail_url
+                .replace("%{width}", &width.to_string())
+                .replace("%{height}", &height.to_string())
+        };
+
+        VideoMetadata {
+            title: video.title.to_owned(),
+            channel_id: video.user_id.to_owned(),
+            channel_title: video.user_name.to_owned(),
+            published_at: DateTime::parse_from_rfc3339(&video.published_at)
+                .ok()
+                .map(|published_at| published_at.naive_utc()),
+            category_id: None,
+            thumbnails: VideoThumbnails {
+                default: thumbnail_url(120, 90),
+                medium: Some(thumbnail_url(320, 180)),
+                high: Some(thumbnail_url(480, 360)),
+                maxres: Some(thumbnail_url(1280, 720)),
+            },
+            // Durations come as "3h8m33s", the same shape as a `t=` offset
+            duration_seconds: parse_timestamp_offset(&video.duration),
+            is_live_broadcast: false,
+        }
+    }
+}
+
+
+// The Helix api needs an app client id and token, TWITCH_CLIENT_ID and TWITCH_ACCESS_TOKEN
+pub fn fetch_video_metadata(twitch_video_id: &str) -> Result<VideoMetadata, &'static str> {
+    let (client_id, access_token) = match (env::var("TWITCH_CLIENT_ID"), env::var("TWITCH_ACCESS_TOKEN")) {
+        (Ok(client_id), Ok(access_token)) => (client_id, access_token),
+        _ => return Err("Twitch metadata is not configured")
+    };
+
+    let twitch_query_url = format!("https://api.twitch.tv/helix/videos?id={}", twitch_video_id);
+
+    let response_maybe = reqwest::blocking::Client::new()
+        .get(twitch_query_url.as_str())
+        .header("Client-Id", client_id)
+        .bearer_auth(access_token)
+        .send();
+
+    let response = match response_maybe {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid twitch response")
+    };
+
+    let decoded_video_details = match response.json::<TwitchVideoResponse>() {
+        Ok(decoded) => decoded,
+        Err(_err) => return Err("Invalid twitch json response format")
+    };
+
+    match decoded_video_details.data.first() {
+        Some(video) => Ok(VideoMetadata::from_twitch(video)),
+        None => Err("Twitch video not found")
+    }
+}
@@ -0,0 +1,48 @@
+use chrono::NaiveDateTime;
+
+use super::{VideoMetadata, VideoThumbnails};
+use crate::ws_dto::VimeoOembedResponse;
+
+
+impl VideoMetadata {
+    pub fn from_vimeo(oembed: &VimeoOembedResponse) -> VideoMetadata {
+        VideoMetadata {
+            title: oembed.title.to_owned(),
+            channel_id: oembed.author_url.to_owned(),
+            channel_title: oembed.author_name.to_owned(),
+            published_at: oembed.upload_date.as_ref()
+                .and_then(|upload_date| NaiveDateTime::parse_from_str(upload_date, "%Y-%m-%d %H:%M:%S").ok()),
+            category_id: None,
+            thumbnails: VideoThumbnails {
+                default: oembed.thumbnail_url.to_owned(),
+                medium: None,
+                high: None,
+                maxres: None,
+            },
+            duration_seconds: oembed.duration,
+            is_live_broadcast: false,
+        }
+    }
+}
+
+
+// Vimeo's oEmbed endpoint needs no api key and covers public and unlisted videos
+pub fn fetch_video_metadata(vimeo_video_id: &str) -> Result<VideoMetadata, &'static str> {
+    let vimeo_query_url = format!(
+        "https://vimeo.com/api/oembed.json?url=https://vimeo.com/{}", vimeo_video_id
+    );
+
+    let response = match reqwest::blocking::get(vimeo_query_url.as_str()) {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid vimeo response")
+    };
+
+    if !response.status().is_success() {
+        return Err("Vimeo video not found");
+    }
+
+    match response.json::<VimeoOembedResponse>() {
+        Ok(oembed) => Ok(VideoMetadata::from_vimeo(&oembed)),
+        Err(_err) => Err("Invalid vimeo json response format")
+    }
+}
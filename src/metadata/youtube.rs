@@ -0,0 +1,117 @@
+use std::env;
+use chrono::DateTime;
+
+use super::{PlaylistMetadata, VideoMetadata, VideoThumbnails};
+use crate::ws_dto::{
+    YoutubePlaylistItemResponse, YoutubePlaylistResponse, YoutubeVideoResponse, YoutubeVideoResponseItem,
+    YoutubeVideoResponseItemSnippetThumbnailDetail,
+};
+use crate::utils::parse_iso8601_duration;
+
+
+impl VideoMetadata {
+    pub fn from_youtube(item: &YoutubeVideoResponseItem) -> VideoMetadata {
+        let snippet = &item.snippet;
+        let thumbnail_url = |detail: &Option<YoutubeVideoResponseItemSnippetThumbnailDetail>| {
+            detail.as_ref().map(|d| d.url.to_owned())
+        };
+
+        VideoMetadata {
+            title: snippet.title.to_owned(),
+            channel_id: snippet.channelId.to_owned().unwrap_or_default(),
+            channel_title: snippet.channelTitle.to_owned().unwrap_or_default(),
+            published_at: snippet.publishedAt.as_ref()
+                .and_then(|published_at| DateTime::parse_from_rfc3339(published_at).ok())
+                .map(|published_at| published_at.naive_utc()),
+            category_id: snippet.categoryId.to_owned(),
+            thumbnails: VideoThumbnails {
+                default: snippet.thumbnails.default.url.to_owned(),
+                medium: thumbnail_url(&snippet.thumbnails.medium),
+                high: thumbnail_url(&snippet.thumbnails.high),
+                maxres: thumbnail_url(&snippet.thumbnails.maxres),
+            },
+            duration_seconds: item.contentDetails.as_ref()
+                .and_then(|details| parse_iso8601_duration(&details.duration)),
+            // liveBroadcastContent is "none" for regular uploads, "live" or "upcoming" otherwise
+            is_live_broadcast: snippet.liveBroadcastContent.as_ref()
+                .is_some_and(|content| content != "none"),
+        }
+    }
+}
+
+
+pub fn fetch_video_metadata(youtube_video_id: &str) -> Result<VideoMetadata, &'static str> {
+    let youtube_api_key = env::var("YOUTUBE_API_KEY").unwrap();
+    let youtube_query_url = format!(
+        "https://www.googleapis.com/youtube/v3/videos?id={}&key={}&part=snippet,contentDetails",
+        youtube_video_id, youtube_api_key
+    );
+
+    let response = match reqwest::blocking::get(youtube_query_url.as_str()) {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid youtube response")
+    };
+
+    let decoded_video_details = match response.json::<YoutubeVideoResponse>() {
+        Ok(decoded) => decoded,
+        Err(_err) => return Err("Invalid youtube json response format")
+    };
+
+    match decoded_video_details.items.first() {
+        Some(item) => Ok(VideoMetadata::from_youtube(item)),
+        None => Err("Youtube video not found")
+    }
+}
+
+// Mixes (RD...) and private lists such as Watch Later are not returned by the API and come back as not found
+pub fn fetch_playlist_metadata(playlist_id: &str) -> Result<PlaylistMetadata, &'static str> {
+    let youtube_api_key = env::var("YOUTUBE_API_KEY").unwrap();
+    let youtube_query_url = format!(
+        "https://www.googleapis.com/youtube/v3/playlists?id={}&key={}&part=snippet,contentDetails",
+        playlist_id, youtube_api_key
+    );
+
+    let response = match reqwest::blocking::get(youtube_query_url.as_str()) {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid youtube response")
+    };
+
+    let decoded_playlist_details = match response.json::<YoutubePlaylistResponse>() {
+        Ok(decoded) => decoded,
+        Err(_err) => return Err("Invalid youtube json response format")
+    };
+
+    match decoded_playlist_details.items.first() {
+        Some(item) => Ok(PlaylistMetadata {
+            title: item.snippet.title.to_owned(),
+            channel_id: item.snippet.channelId.to_owned(),
+            channel_title: item.snippet.channelTitle.to_owned(),
+            item_count: item.contentDetails.as_ref().map(|details| details.itemCount),
+            thumbnail_url: item.snippet.thumbnails.default.url.to_owned(),
+        }),
+        None => Err("Youtube playlist not found")
+    }
+}
+
+pub fn fetch_playlist_position(playlist_id: &str, youtube_video_id: &str) -> Result<u32, &'static str> {
+    let youtube_api_key = env::var("YOUTUBE_API_KEY").unwrap();
+    let youtube_query_url = format!(
+        "https://www.googleapis.com/youtube/v3/playlistItems?playlistId={}&videoId={}&key={}&part=snippet",
+        playlist_id, youtube_video_id, youtube_api_key
+    );
+
+    let response = match reqwest::blocking::get(youtube_query_url.as_str()) {
+        Ok(response) => response,
+        Err(_err) => return Err("Invalid youtube response")
+    };
+
+    let decoded_playlist_items = match response.json::<YoutubePlaylistItemResponse>() {
+        Ok(decoded) => decoded,
+        Err(_err) => return Err("Invalid youtube json response format")
+    };
+
+    match decoded_playlist_items.items.first() {
+        Some(item) => Ok(item.snippet.position + 1),
+        None => Err("Video is not in the youtube playlist")
+    }
+}
@@ -1,6 +1,7 @@
-use super::schema::{usermaster, userfriends, videos, uservideos};
+use super::schema::{usermaster, userfriends, videos, uservideos, messages, videoreactions, notifications, videoshares, invitecodes, inviteredemptions, videoviews, playlists, playlistvideos};
 use serde::{Serialize};
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::sql_types::{Array, BigInt, Date, Double, Nullable, Text, Timestamp};
 
 
 #[derive(Queryable, Clone, Serialize)]
@@ -15,18 +16,35 @@ pub struct Usermaster {
     pub created_at: NaiveDateTime,
 
     #[serde(skip_serializing)]
-    pub updated_at: Option<NaiveDateTime>
+    pub updated_at: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing)]
+    pub is_discoverable: bool,
+
+    #[serde(skip_serializing)]
+    pub last_seen_at: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing)]
+    pub last_video_id: Option<i64>
 }
 
 
-#[derive(Queryable)]
+#[derive(Queryable, Clone)]
 pub struct UserFriend {
     pub id: i64,
     pub user_google_uid: String,
     pub friend_google_uid: String,
     pub is_friend_excluded: bool,
     pub created_at: NaiveDateTime,
-    pub updated_at: Option<NaiveDateTime>
+    pub updated_at: Option<NaiveDateTime>,
+    pub is_friend_blocked: bool
+}
+
+impl UserFriend {
+    // Whether this side of the friendship lets the other one see and reach them
+    pub fn allows_interaction(&self) -> bool {
+        !self.is_friend_excluded && !self.is_friend_blocked
+    }
 }
 
 #[derive(Serialize)]
@@ -40,40 +58,150 @@ pub struct UserFriendEntity {
     pub friend_google_uid: String,
     pub friend: Usermaster,
     pub is_friend_excluded: bool,
+    pub is_friend_blocked: bool,
 
     #[serde(skip_serializing)]
     pub created_at: NaiveDateTime,
 
     #[serde(skip_serializing)]
-    pub updated_at: Option<NaiveDateTime>
+    pub updated_at: Option<NaiveDateTime>,
+
+    // Both sides have the friendship and neither has excluded or blocked the other
+    #[serde(skip_serializing)]
+    pub can_interact: bool,
+
+    // Only filled in while the friend is offline and neither side has excluded or blocked the other, for "last seen 2h ago watching ..."
+    pub last_seen_at_in_milliseconds: Option<i64>,
+    pub last_video: Option<serde_json::Value>
 }
 
 impl UserFriendEntity {
-    pub fn from(user_friend_row: &UserFriend, user: &Usermaster) -> UserFriendEntity {
+    pub fn from(user_friend_row: &UserFriend, friend_row_back: Option<&UserFriend>, user: &Usermaster) -> UserFriendEntity {
         UserFriendEntity {
             id: user_friend_row.id,
             user_google_uid: user_friend_row.user_google_uid.to_owned(),
             friend_google_uid: user_friend_row.friend_google_uid.to_owned(),
             friend: user.clone(),
             is_friend_excluded: user_friend_row.is_friend_excluded,
+            is_friend_blocked: user_friend_row.is_friend_blocked,
             created_at: user_friend_row.created_at,
-            updated_at: user_friend_row.updated_at
+            updated_at: user_friend_row.updated_at,
+            can_interact: user_friend_row.allows_interaction() && friend_row_back.is_some_and(UserFriend::allows_interaction),
+            last_seen_at_in_milliseconds: None,
+            last_video: None
         }
     }
 }
 
 
-#[derive(Queryable)]
+#[derive(Queryable, Clone)]
 pub struct Video {
     pub id: i64,
     pub video_url: String,
-    pub youtube_video_id: String,
+    pub external_id: String,
     pub video_title: String,
     pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+    pub channel_id: Option<String>,
+    pub channel_title: Option<String>,
+    pub published_at: Option<NaiveDateTime>,
+    pub category_id: Option<String>,
+    pub thumbnail_default_url: Option<String>,
+    pub thumbnail_medium_url: Option<String>,
+    pub thumbnail_high_url: Option<String>,
+    pub thumbnail_maxres_url: Option<String>,
+    pub duration_seconds: Option<i64>,
+    pub is_live_broadcast: bool,
+    pub platform: String,
+    pub view_count: i64,
+    pub unique_viewer_count: i64
+}
+
+#[derive(Queryable, Clone)]
+pub struct Message {
+    pub id: i64,
+    pub sender_google_uid: String,
+    pub recipient_google_uid: String,
+    pub body: String,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub read_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+// An event that could not be sent live, kept until the recipient next logs in
+#[derive(Queryable, Clone)]
+pub struct Notification {
+    pub id: i64,
+    pub recipient_google_uid: String,
+    pub action: String,
+    pub payload: String,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub read_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Clone)]
+pub struct VideoReaction {
+    pub id: i64,
+    pub video_id: i64,
+    pub watcher_google_uid: String,
+    pub reactor_google_uid: String,
+    pub emoji: Option<String>,
+    pub comment: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Clone)]
+pub struct VideoShare {
+    pub id: i64,
+    pub video_id: i64,
+    pub sender_google_uid: String,
+    pub recipient_google_uid: String,
+    pub note: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Clone)]
+pub struct InviteCode {
+    pub id: i64,
+    pub code: String,
+    pub inviter_google_uid: String,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Clone)]
+pub struct Playlist {
+    pub id: i64,
+    pub platform: String,
+    pub external_id: String,
+    pub title: String,
+    pub channel_id: Option<String>,
+    pub channel_title: Option<String>,
+    pub item_count: Option<i64>,
+    pub thumbnail_url: Option<String>,
+    pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>
 }
 
 #[derive(Queryable)]
+pub struct PlaylistVideo {
+    pub id: i64,
+    pub playlist_id: i64,
+    pub video_id: i64,
+    pub position: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Clone)]
 pub struct UserVideo {
     pub id: i64,
     pub user_id: i64,
@@ -105,11 +233,39 @@ pub struct NewUserFriend<'a> {
 #[table_name="videos"]
 pub struct NewVideo<'a> {
     pub video_url: &'a str,
-    pub youtube_video_id: &'a str,
+    pub external_id: &'a str,
     pub video_title: &'a str,
+    pub channel_id: Option<&'a str>,
+    pub channel_title: Option<&'a str>,
+    pub published_at: Option<NaiveDateTime>,
+    pub category_id: Option<&'a str>,
+    pub thumbnail_default_url: Option<&'a str>,
+    pub thumbnail_medium_url: Option<&'a str>,
+    pub thumbnail_high_url: Option<&'a str>,
+    pub thumbnail_maxres_url: Option<&'a str>,
+    pub duration_seconds: Option<i64>,
+    pub is_live_broadcast: bool,
+    pub platform: &'a str,
     pub created_at: NaiveDateTime,
 }
 
+#[derive(AsChangeset)]
+#[table_name="videos"]
+pub struct VideoMetadataUpdate<'a> {
+    pub video_title: &'a str,
+    pub channel_id: Option<&'a str>,
+    pub channel_title: Option<&'a str>,
+    pub published_at: Option<NaiveDateTime>,
+    pub category_id: Option<&'a str>,
+    pub thumbnail_default_url: Option<&'a str>,
+    pub thumbnail_medium_url: Option<&'a str>,
+    pub thumbnail_high_url: Option<&'a str>,
+    pub thumbnail_maxres_url: Option<&'a str>,
+    pub duration_seconds: Option<i64>,
+    pub is_live_broadcast: bool,
+    pub updated_at: NaiveDateTime,
+}
+
 #[derive(Insertable)]
 #[table_name="uservideos"]
 pub struct NewUserVideo {
@@ -117,3 +273,200 @@ pub struct NewUserVideo {
     pub video_id: i64,
     pub created_at: NaiveDateTime,
 }
+
+#[derive(Insertable)]
+#[table_name="messages"]
+pub struct NewMessage<'a> {
+    pub sender_google_uid: &'a str,
+    pub recipient_google_uid: &'a str,
+    pub body: &'a str,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="videoreactions"]
+pub struct NewVideoReaction<'a> {
+    pub video_id: i64,
+    pub watcher_google_uid: &'a str,
+    pub reactor_google_uid: &'a str,
+    pub emoji: Option<&'a str>,
+    pub comment: Option<&'a str>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="notifications"]
+pub struct NewNotification<'a> {
+    pub recipient_google_uid: &'a str,
+    pub action: &'a str,
+    pub payload: &'a str,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="videoshares"]
+pub struct NewVideoShare<'a> {
+    pub video_id: i64,
+    pub sender_google_uid: &'a str,
+    pub recipient_google_uid: &'a str,
+    pub note: Option<&'a str>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="videoviews"]
+pub struct NewVideoView {
+    pub video_id: i64,
+    pub user_id: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="playlists"]
+pub struct NewPlaylist<'a> {
+    pub platform: &'a str,
+    pub external_id: &'a str,
+    pub title: &'a str,
+    pub channel_id: Option<&'a str>,
+    pub channel_title: Option<&'a str>,
+    pub item_count: Option<i64>,
+    pub thumbnail_url: Option<&'a str>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(AsChangeset)]
+#[table_name="playlists"]
+pub struct PlaylistMetadataUpdate<'a> {
+    pub title: &'a str,
+    pub channel_id: Option<&'a str>,
+    pub channel_title: Option<&'a str>,
+    pub item_count: Option<i64>,
+    pub thumbnail_url: Option<&'a str>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="playlistvideos"]
+pub struct NewPlaylistVideo {
+    pub playlist_id: i64,
+    pub video_id: i64,
+    pub position: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="invitecodes"]
+pub struct NewInviteCode<'a> {
+    pub code: &'a str,
+    pub inviter_google_uid: &'a str,
+    pub max_uses: Option<i32>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="inviteredemptions"]
+pub struct NewInviteRedemption<'a> {
+    pub invite_code_id: i64,
+    pub invitee_google_uid: &'a str,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(QueryableByName)]
+pub struct FriendSuggestion {
+    #[sql_type = "Text"]
+    pub uid: String,
+    #[sql_type = "Text"]
+    pub full_name: String,
+    #[sql_type = "Text"]
+    pub image_url: String,
+    #[sql_type = "BigInt"]
+    pub mutual_friend_count: i64,
+    #[sql_type = "BigInt"]
+    pub shared_video_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct PublicProfile {
+    #[sql_type = "Text"]
+    pub uid: String,
+    #[sql_type = "Text"]
+    pub full_name: String,
+    #[sql_type = "Text"]
+    pub image_url: String,
+}
+
+#[derive(QueryableByName)]
+pub struct TrendingVideo {
+    #[sql_type = "BigInt"]
+    pub video_id: i64,
+    #[sql_type = "BigInt"]
+    pub viewer_count: i64,
+    #[sql_type = "Timestamp"]
+    pub last_watched_at: NaiveDateTime,
+    // Most recent viewer first
+    #[sql_type = "Array<Text>"]
+    pub viewer_google_uids: Vec<String>,
+}
+
+#[derive(QueryableByName)]
+pub struct Recommendation {
+    #[sql_type = "BigInt"]
+    pub video_id: i64,
+    #[sql_type = "Double"]
+    pub score: f64,
+    #[sql_type = "BigInt"]
+    pub friend_viewer_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct WatchCountBucket {
+    #[sql_type = "Date"]
+    pub bucket: NaiveDate,
+    #[sql_type = "BigInt"]
+    pub video_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct WatchTimeTotals {
+    #[sql_type = "BigInt"]
+    pub video_count: i64,
+    #[sql_type = "BigInt"]
+    pub watch_seconds: i64,
+    #[sql_type = "BigInt"]
+    pub unknown_duration_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct TopWatchGroup {
+    #[sql_type = "Text"]
+    pub group_id: String,
+    #[sql_type = "Nullable<Text>"]
+    pub group_title: Option<String>,
+    #[sql_type = "BigInt"]
+    pub video_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct TasteOverlap {
+    #[sql_type = "Text"]
+    pub uid: String,
+    #[sql_type = "Text"]
+    pub full_name: String,
+    #[sql_type = "Text"]
+    pub image_url: String,
+    #[sql_type = "BigInt"]
+    pub shared_video_count: i64,
+    #[sql_type = "Double"]
+    pub similarity: f64,
+}
+
+#[derive(QueryableByName)]
+pub struct VideoPopularity {
+    #[sql_type = "BigInt"]
+    pub video_id: i64,
+    #[sql_type = "BigInt"]
+    pub view_count: i64,
+    #[sql_type = "BigInt"]
+    pub unique_viewer_count: i64,
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use url::Url;
+
+use crate::utils::{parse_timestamp_offset, parse_url_lenient, parse_youtube_url};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    YouTube,
+    Vimeo,
+    Dailymotion,
+    TwitchVod,
+}
+
+impl Platform {
+    // The value stored in `videos.platform` and sent to clients
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::YouTube => "youtube",
+            Platform::Vimeo => "vimeo",
+            Platform::Dailymotion => "dailymotion",
+            Platform::TwitchVod => "twitch",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoUrl {
+    pub platform: Platform,
+    pub external_id: String,
+    pub start_seconds: Option<i64>,
+    pub playlist_id: Option<String>,
+    pub playlist_index: Option<u32>,
+}
+
+impl VideoUrl {
+    // The form stored in `videos` and broadcast to friends, free of tracking and playlist params
+    pub fn canonical_url(&self) -> String {
+        match self.platform {
+            Platform::YouTube => format!("https://www.youtube.com/watch?v={}", self.external_id),
+            Platform::Vimeo => format!("https://vimeo.com/{}", self.external_id),
+            Platform::Dailymotion => format!("https://www.dailymotion.com/video/{}", self.external_id),
+            Platform::TwitchVod => format!("https://www.twitch.tv/videos/{}", self.external_id),
+        }
+    }
+
+    // Opens the video at the given offset, using each site's own start parameter
+    pub fn url_at_position(&self, position_in_seconds: i64) -> String {
+        let canonical_url = self.canonical_url();
+        if position_in_seconds <= 0 {
+            return canonical_url;
+        }
+
+        match self.platform {
+            Platform::YouTube => format!("{}&t={}s", canonical_url, position_in_seconds),
+            Platform::Vimeo => format!("{}#t={}s", canonical_url, position_in_seconds),
+            Platform::Dailymotion => format!("{}?start={}", canonical_url, position_in_seconds),
+            Platform::TwitchVod => format!(
+                "{}?t={}h{}m{}s", canonical_url,
+                position_in_seconds / 3600, (position_in_seconds % 3600) / 60, position_in_seconds % 60
+            ),
+        }
+    }
+
+    // Like url_at_position, but keeps the playlist so the player carries on through the list afterwards
+    pub fn follow_along_url(&self, position_in_seconds: i64) -> String {
+        let url_at_position = self.url_at_position(position_in_seconds);
+
+        match (self.platform, &self.playlist_id, self.playlist_index) {
+            (Platform::YouTube, Some(playlist_id), Some(index)) => format!("{}&list={}&index={}", url_at_position, playlist_id, index),
+            (Platform::YouTube, Some(playlist_id), None) => format!("{}&list={}", url_at_position, playlist_id),
+            _ => url_at_position,
+        }
+    }
+
+    fn without_context(platform: Platform, external_id: &str, start_seconds: Option<i64>) -> VideoUrl {
+        VideoUrl {
+            platform,
+            external_id: external_id.to_string(),
+            start_seconds,
+            playlist_id: None,
+            playlist_index: None,
+        }
+    }
+}
+
+pub fn parse_video_url(video_url: &str) -> Option<VideoUrl> {
+    if let Some(youtube_url) = parse_youtube_url(video_url) {
+        return Some(VideoUrl {
+            platform: Platform::YouTube,
+            external_id: youtube_url.video_id,
+            start_seconds: youtube_url.start_seconds,
+            playlist_id: youtube_url.playlist_id,
+            playlist_index: youtube_url.index,
+        });
+    }
+
+    let parsed_url = parse_url_lenient(video_url)?;
+    let host = parsed_url.host_str()?.to_lowercase();
+    let host = host.trim_start_matches("www.");
+
+    match host {
+        "vimeo.com" | "player.vimeo.com" => parse_vimeo_url(&parsed_url),
+        "dailymotion.com" | "dai.ly" | "geo.dailymotion.com" => parse_dailymotion_url(&parsed_url, host),
+        "twitch.tv" | "m.twitch.tv" | "player.twitch.tv" => parse_twitch_vod_url(&parsed_url),
+        _ => None,
+    }
+}
+
+fn path_segments(parsed_url: &Url) -> Vec<&str> {
+    parsed_url.path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+fn query_param(parsed_url: &Url, name: &str) -> Option<String> {
+    parsed_url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn is_numeric_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+}
+
+// vimeo.com/ID, vimeo.com/channels/NAME/ID, vimeo.com/groups/NAME/videos/ID, player.vimeo.com/video/ID
+fn parse_vimeo_url(parsed_url: &Url) -> Option<VideoUrl> {
+    let video_id = path_segments(parsed_url).into_iter().find(|segment| is_numeric_id(segment))?;
+    let start_seconds = parsed_url.fragment()
+        .and_then(|fragment| fragment.strip_prefix("t="))
+        .and_then(parse_timestamp_offset);
+
+    Some(VideoUrl::without_context(Platform::Vimeo, video_id, start_seconds))
+}
+
+// dailymotion.com/video/ID, dailymotion.com/embed/video/ID, dai.ly/ID, geo.dailymotion.com/player.html?video=ID
+fn parse_dailymotion_url(parsed_url: &Url, host: &str) -> Option<VideoUrl> {
+    let segments = path_segments(parsed_url);
+    let video_id = match (host, segments.as_slice()) {
+        ("dai.ly", [id]) => id.to_string(),
+        ("dailymotion.com", ["video", id, ..]) | ("dailymotion.com", ["embed", "video", id, ..]) => {
+            // Old style urls carry a slug after the id: /video/x7tgad0_some-title
+            id.split('_').next()?.to_string()
+        },
+        ("geo.dailymotion.com", _) => query_param(parsed_url, "video")?,
+        _ => return None,
+    };
+
+    if video_id.is_empty() || !video_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let start_seconds = query_param(parsed_url, "start").and_then(|start| parse_timestamp_offset(&start));
+
+    Some(VideoUrl::without_context(Platform::Dailymotion, &video_id, start_seconds))
+}
+
+// twitch.tv/videos/ID, m.twitch.tv/videos/ID, player.twitch.tv/?video=vID
+fn parse_twitch_vod_url(parsed_url: &Url) -> Option<VideoUrl> {
+    let video_id = match path_segments(parsed_url).as_slice() {
+        ["videos", id] => id.to_string(),
+        [] => query_param(parsed_url, "video")?.trim_start_matches('v').to_string(),
+        _ => return None,
+    };
+
+    if !is_numeric_id(&video_id) {
+        return None;
+    }
+    let start_seconds = query_param(parsed_url, "t").and_then(|t| parse_timestamp_offset(&t));
+
+    Some(VideoUrl::without_context(Platform::TwitchVod, &video_id, start_seconds))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type ExpectedVideoUrl<'a> = (Platform, &'a str, Option<i64>);
+
+    #[test]
+    fn parses_urls_for_every_platform() {
+        use Platform::*;
+
+        let cases: Vec<(&str, Option<ExpectedVideoUrl>)> = vec![
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42", Some((YouTube, "dQw4w9WgXcQ", Some(42)))),
+            ("https://youtu.be/dQw4w9WgXcQ", Some((YouTube, "dQw4w9WgXcQ", None))),
+            ("https://vimeo.com/76979871", Some((Vimeo, "76979871", None))),
+            ("https://vimeo.com/76979871#t=1m5s", Some((Vimeo, "76979871", Some(65)))),
+            ("https://vimeo.com/channels/staffpicks/76979871", Some((Vimeo, "76979871", None))),
+            ("https://vimeo.com/groups/shortfilms/videos/76979871", Some((Vimeo, "76979871", None))),
+            ("https://player.vimeo.com/video/76979871?h=8272103f6e", Some((Vimeo, "76979871", None))),
+            ("https://vimeo.com/channels/staffpicks", None),
+            ("https://www.dailymotion.com/video/x7tgad0", Some((Dailymotion, "x7tgad0", None))),
+            ("https://www.dailymotion.com/video/x7tgad0_some-title?start=30", Some((Dailymotion, "x7tgad0", Some(30)))),
+            ("https://www.dailymotion.com/embed/video/x7tgad0", Some((Dailymotion, "x7tgad0", None))),
+            ("https://dai.ly/x7tgad0", Some((Dailymotion, "x7tgad0", None))),
+            ("https://geo.dailymotion.com/player.html?video=x7tgad0", Some((Dailymotion, "x7tgad0", None))),
+            ("https://www.dailymotion.com/user/someone", None),
+            ("https://www.twitch.tv/videos/1234567890", Some((TwitchVod, "1234567890", None))),
+            ("https://www.twitch.tv/videos/1234567890?t=1h2m3s", Some((TwitchVod, "1234567890", Some(3723)))),
+            ("https://m.twitch.tv/videos/1234567890", Some((TwitchVod, "1234567890", None))),
+            ("https://player.twitch.tv/?video=v1234567890&parent=example.com", Some((TwitchVod, "1234567890", None))),
+            ("https://www.twitch.tv/somestreamer", None),
+            ("https://example.com/videos/1234567890", None),
+        ];
+
+        for (video_url, expected) in cases {
+            let parsed = parse_video_url(video_url)
+                .map(|video_url| (video_url.platform, video_url.external_id, video_url.start_seconds));
+            let expected = expected
+                .map(|(platform, external_id, start_seconds)| (platform, external_id.to_string(), start_seconds));
+            assert_eq!(parsed, expected, "parsing {}", video_url);
+        }
+    }
+
+    #[test]
+    fn canonical_urls_per_platform() {
+        let cases = vec![
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ&list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            ("https://player.vimeo.com/video/76979871?h=8272103f6e", "https://vimeo.com/76979871"),
+            ("https://dai.ly/x7tgad0", "https://www.dailymotion.com/video/x7tgad0"),
+            ("https://m.twitch.tv/videos/1234567890?t=10s", "https://www.twitch.tv/videos/1234567890"),
+        ];
+
+        for (video_url, canonical_url) in cases {
+            assert_eq!(parse_video_url(video_url).unwrap().canonical_url(), canonical_url);
+        }
+    }
+
+    #[test]
+    fn urls_at_position_round_trip() {
+        let video_urls = vec![
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://vimeo.com/76979871",
+            "https://www.dailymotion.com/video/x7tgad0",
+            "https://www.twitch.tv/videos/1234567890",
+        ];
+
+        for video_url in video_urls {
+            let parsed = parse_video_url(video_url).unwrap();
+            let at_position = parse_video_url(&parsed.url_at_position(3723)).unwrap();
+            assert_eq!(at_position.start_seconds, Some(3723), "round trip of {}", video_url);
+        }
+    }
+
+    #[test]
+    fn follow_along_urls_keep_the_playlist() {
+        let video_url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI&index=4";
+        let parsed = parse_video_url(video_url).unwrap();
+        let followed = parse_video_url(&parsed.follow_along_url(90)).unwrap();
+
+        assert_eq!(followed.start_seconds, Some(90));
+        assert_eq!(followed.playlist_id, parsed.playlist_id);
+        assert_eq!(followed.playlist_index, Some(4));
+
+        let vimeo = parse_video_url("https://vimeo.com/76979871").unwrap();
+        assert_eq!(vimeo.follow_along_url(90), vimeo.url_at_position(90));
+    }
+}
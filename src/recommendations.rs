@@ -0,0 +1,56 @@
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::QueryResult;
+
+use tubepeek_server_rust::models::Recommendation;
+use tubepeek_server_rust::repositories::DiscoveryRepository;
+
+use crate::StorageBackend;
+
+
+// Only each user's most recent watches feed the co-watch pairs, which keeps the self join per user bounded
+const SIMILARITY_HISTORY_DEPTH: i64 = 200;
+// Neighbours kept per video, the recommendation query never looks past these
+const SIMILAR_VIDEOS_PER_VIDEO: i64 = 50;
+// How much of the user's own history is used to find candidates
+const RECOMMENDATION_SEED_DEPTH: i64 = 50;
+// Every friend who watched a candidate adds this much to its score multiplier
+const FRIEND_VIEWER_WEIGHT: f64 = 0.5;
+
+const DEFAULT_REBUILD_INTERVAL_SECS: u64 = 60 * 60;
+// The rebuild scans every user's recent history, running it back to back would keep the database busy
+const MIN_REBUILD_INTERVAL_SECS: u64 = 60;
+
+pub fn rebuild_video_similarities(discovery: &dyn DiscoveryRepository) -> QueryResult<usize> {
+    discovery.rebuild_video_similarities(SIMILARITY_HISTORY_DEPTH, SIMILAR_VIDEOS_PER_VIDEO, Utc::now().naive_utc())
+}
+
+// Rebuilds once at start up and then every RECOMMENDATIONS_REBUILD_INTERVAL_SECS
+pub fn spawn_similarity_rebuilds(storage: StorageBackend) {
+    let rebuild_interval = std::env::var("RECOMMENDATIONS_REBUILD_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_REBUILD_INTERVAL_SECS);
+
+    let rebuild_interval = if rebuild_interval < MIN_REBUILD_INTERVAL_SECS {
+        println!("RECOMMENDATIONS_REBUILD_INTERVAL_SECS is below {}, using that instead", MIN_REBUILD_INTERVAL_SECS);
+        MIN_REBUILD_INTERVAL_SECS
+    } else {
+        rebuild_interval
+    };
+
+    thread::spawn(move || loop {
+        match storage.with_storage(|storage| rebuild_video_similarities(storage.discovery)) {
+            Ok(Ok(pair_count)) => println!("Rebuilt video similarities, {} pairs.", pair_count),
+            Ok(Err(err)) => println!("Failed to rebuild video similarities: {:?}", err),
+            Err(err) => println!("No connection for video similarities rebuild: {:?}", err),
+        }
+        thread::sleep(Duration::from_secs(rebuild_interval));
+    });
+}
+
+pub fn load_recommendations(google_user_id: &str, limit: i64, offset: i64, discovery: &dyn DiscoveryRepository) -> QueryResult<Vec<Recommendation>> {
+    discovery.recommendations(google_user_id, RECOMMENDATION_SEED_DEPTH, FRIEND_VIEWER_WEIGHT, limit, offset)
+}
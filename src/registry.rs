@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::WsConnectedClientMetadata;
+
+
+// Who is connected on which socket. The server owns it and hands it to every handler.
+// One lock guards both indexes so they never disagree about whose socket is whose. Sending happens
+// under the read lock, registering, unregistering and update() take the write lock just for the
+// change, never while sending.
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    connections: RwLock<Connections>,
+}
+
+#[derive(Debug, Default)]
+pub struct Connections {
+    by_connection_id: HashMap<u32, WsConnectedClientMetadata>,
+    // A user has a socket per open tab or device
+    by_user: HashMap<String, Vec<u32>>,
+}
+
+impl ConnectionRegistry {
+    pub fn read(&self) -> RwLockReadGuard<'_, Connections> {
+        self.connections.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Connections> {
+        self.connections.write().unwrap()
+    }
+
+    pub fn user_of(&self, connection_id: u32) -> Option<String> {
+        self.read().get(connection_id).map(|conn_metadata| conn_metadata.googleUserId.to_owned())
+    }
+
+    // Returns None when the connection is not registered
+    pub fn update<T>(&self, connection_id: u32, change: impl FnOnce(&mut WsConnectedClientMetadata) -> T) -> Option<T> {
+        self.write().get_mut(connection_id).map(change)
+    }
+
+    // The message is built from the sender's state, returns false when the connection is not registered
+    pub fn broadcast_to_online_friends(&self, connection_id: u32, message: impl FnOnce(&WsConnectedClientMetadata) -> String) -> bool {
+        let connected_clients = self.read();
+        match connected_clients.get(connection_id) {
+            Some(conn_metadata) => {
+                connected_clients.send_to_online_friends(conn_metadata, &message(conn_metadata));
+                true
+            },
+            None => false
+        }
+    }
+}
+
+impl Connections {
+    // Replaces whatever was registered on the same socket before
+    pub fn register(&mut self, conn_metadata: WsConnectedClientMetadata) {
+        let connection_id = conn_metadata.socketId;
+        self.unregister(connection_id);
+
+        self.by_user.entry(conn_metadata.googleUserId.to_owned()).or_default().push(connection_id);
+        self.by_connection_id.insert(connection_id, conn_metadata);
+    }
+
+    pub fn unregister(&mut self, connection_id: u32) -> Option<WsConnectedClientMetadata> {
+        let conn_metadata = self.by_connection_id.remove(&connection_id)?;
+
+        if let Some(connection_ids) = self.by_user.get_mut(&conn_metadata.googleUserId) {
+            connection_ids.retain(|user_connection_id| *user_connection_id != connection_id);
+            if connection_ids.is_empty() {
+                self.by_user.remove(&conn_metadata.googleUserId);
+            }
+        }
+        Some(conn_metadata)
+    }
+
+    pub fn get(&self, connection_id: u32) -> Option<&WsConnectedClientMetadata> {
+        self.by_connection_id.get(&connection_id)
+    }
+
+    // Changing googleUserId through this would leave the user index stale, register again instead
+    pub fn get_mut(&mut self, connection_id: u32) -> Option<&mut WsConnectedClientMetadata> {
+        self.by_connection_id.get_mut(&connection_id)
+    }
+
+    pub fn is_connected(&self, google_user_id: &str) -> bool {
+        self.by_user.contains_key(google_user_id)
+    }
+
+    pub fn connections_of<'a>(&'a self, google_user_id: &str) -> impl Iterator<Item = &'a WsConnectedClientMetadata> + 'a {
+        self.by_user.get(google_user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(move |connection_id| self.by_connection_id.get(connection_id))
+    }
+
+    pub fn send_to_sockets(&self, connection_ids: &[u32], message: &str) {
+        for connection_id in connection_ids {
+            if let Some(conn) = self.get(*connection_id) {
+                let _ = conn.socket.send(message);
+            }
+        }
+    }
+
+    // Returns false when none of the user's sockets took the message
+    pub fn send_to_user(&self, google_user_id: &str, message: &str) -> bool {
+        let mut was_sent = false;
+        for conn in self.connections_of(google_user_id) {
+            was_sent = conn.socket.send(message).is_ok() || was_sent;
+        }
+        was_sent
+    }
+
+    // Takes the two users off each other's online friends on every socket, once one excludes or blocks the other
+    pub fn hide_from_each_other(&mut self, google_user_id: &str, friend_google_user_id: &str) {
+        for (user, hidden_user) in [(google_user_id, friend_google_user_id), (friend_google_user_id, google_user_id)].iter() {
+            for connection_id in self.by_user.get(*user).into_iter().flatten() {
+                if let Some(conn_metadata) = self.by_connection_id.get_mut(connection_id) {
+                    conn_metadata.onlineFriends.retain(|friend| friend.googleUserId != *hidden_user);
+                }
+            }
+        }
+    }
+
+    pub fn send_to_online_friends(&self, conn_metadata: &WsConnectedClientMetadata, message: &str) {
+        for friend in conn_metadata.onlineFriends.iter() {
+            if let Some(conn) = self.get(friend.socketId) {
+                let _ = conn.socket.send(message);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopHandler;
+    impl ws::Handler for NoopHandler {}
+
+    fn connection(connection_id: u32, google_user_id: &str, socket: &ws::Sender) -> WsConnectedClientMetadata {
+        WsConnectedClientMetadata {
+            socketId: connection_id,
+            socket: socket.clone(),
+            googleUserId: google_user_id.to_string(),
+            currentVideo: None,
+            onlineFriends: Box::default(),
+            isAway: false,
+        }
+    }
+
+    #[test]
+    fn user_index_follows_register_and_unregister() {
+        let web_socket = ws::WebSocket::new(|_| NoopHandler).unwrap();
+        let socket = web_socket.broadcaster();
+        let mut connections = Connections::default();
+
+        connections.register(connection(1, "alice", &socket));
+        connections.register(connection(2, "alice", &socket));
+        connections.register(connection(3, "bob", &socket));
+
+        let alice_connections: Vec<u32> = connections.connections_of("alice").map(|conn| conn.socketId).collect();
+        assert_eq!(alice_connections, vec![1, 2]);
+
+        // A socket that identifies as someone else moves over to them
+        connections.register(connection(2, "bob", &socket));
+        assert_eq!(connections.connections_of("alice").count(), 1);
+        assert_eq!(connections.connections_of("bob").count(), 2);
+
+        assert!(connections.unregister(1).is_some());
+        assert!(connections.unregister(1).is_none());
+        assert!(!connections.is_connected("alice"));
+        assert!(connections.is_connected("bob"));
+    }
+
+    #[test]
+    fn tabs_come_and_go_independently() {
+        let web_socket = ws::WebSocket::new(|_| NoopHandler).unwrap();
+        let socket = web_socket.broadcaster();
+        let mut connections = Connections::default();
+
+        for connection_id in 1..=3 {
+            connections.register(connection(connection_id, "alice", &socket));
+        }
+        let alice_connections = |connections: &Connections| -> Vec<u32> {
+            connections.connections_of("alice").map(|conn| conn.socketId).collect()
+        };
+
+        assert_eq!(connections.unregister(2).map(|conn| conn.socketId), Some(2));
+        assert_eq!(alice_connections(&connections), vec![1, 3]);
+        assert!(connections.get(2).is_none());
+
+        // Identifying again on the same socket does not count it twice
+        connections.register(connection(3, "alice", &socket));
+        connections.register(connection(2, "alice", &socket));
+        assert_eq!(alice_connections(&connections), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn the_last_connection_takes_the_user_offline() {
+        let web_socket = ws::WebSocket::new(|_| NoopHandler).unwrap();
+        let socket = web_socket.broadcaster();
+        let mut connections = Connections::default();
+
+        connections.register(connection(1, "alice", &socket));
+        connections.register(connection(2, "alice", &socket));
+
+        connections.unregister(1);
+        assert!(connections.is_connected("alice"));
+
+        connections.unregister(2);
+        assert!(!connections.is_connected("alice"));
+        assert!(!connections.by_user.contains_key("alice"));
+        assert_eq!(connections.connections_of("alice").count(), 0);
+        assert!(!connections.send_to_user("alice", "{}"));
+    }
+}
@@ -0,0 +1,1064 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::QueryResult;
+
+use crate::models::{FriendSuggestion, InviteCode, Message, NewInviteCode, NewMessage, NewNotification, NewPlaylist, NewPlaylistVideo, NewUser, NewUserFriend, NewVideo, NewVideoReaction, NewVideoShare, NewVideoView, Notification, Playlist, PlaylistMetadataUpdate, PlaylistVideo, PublicProfile, Recommendation, TasteOverlap, TopWatchGroup, TrendingVideo, UserFriend, Usermaster, UserVideo, Video, VideoMetadataUpdate, VideoPopularity, VideoReaction, VideoShare, WatchCountBucket, WatchTimeTotals};
+use super::{ChatRepository, DiscoveryRepository, FriendshipRepository, InboxRepository, InviteRepository, ReactionRepository, ShareRepository, StatsRepository, UserRepository, VideoRepository};
+
+
+// Keeps everything in process, for running the server without Postgres in tests
+#[derive(Default)]
+pub struct MemoryRepository {
+    data: Mutex<MemoryData>,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    last_id: i64,
+    users: Vec<Usermaster>,
+    friendships: Vec<UserFriend>,
+    videos: Vec<Video>,
+    notifications: Vec<Notification>,
+    messages: Vec<Message>,
+    reactions: Vec<VideoReaction>,
+    shares: Vec<VideoShare>,
+    invite_codes: Vec<InviteCode>,
+    // (invite_code_id, invitee_google_uid) of every redemption
+    invite_redemptions: Vec<(i64, String)>,
+    // Every view, rewatches included
+    views: Vec<NewVideoView>,
+    // One per video a user has watched at least once
+    user_videos: Vec<UserVideo>,
+    playlists: Vec<Playlist>,
+    playlist_videos: Vec<PlaylistVideo>,
+    similarities: Vec<VideoSimilarity>,
+}
+
+struct VideoSimilarity {
+    video_id: i64,
+    similar_video_id: i64,
+    score: f64,
+}
+
+impl MemoryData {
+    // Ids are unique across tables, which is all the callers rely on
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn friendship_mut(&mut self, google_user_id: &str, friend_google_user_id: &str) -> Option<&mut UserFriend> {
+        self.friendships.iter_mut()
+            .find(|row| row.user_google_uid == google_user_id && row.friend_google_uid == friend_google_user_id)
+    }
+
+    // Rows between the two users, in either direction
+    fn rows_between<'a>(&'a self, google_user_id: &'a str, other_google_user_id: &'a str) -> impl Iterator<Item = &'a UserFriend> + 'a {
+        self.friendships.iter().filter(move |row| {
+            (row.user_google_uid == google_user_id && row.friend_google_uid == other_google_user_id)
+                || (row.user_google_uid == other_google_user_id && row.friend_google_uid == google_user_id)
+        })
+    }
+
+    // Friends with a row each way where both rows pass the filter
+    fn friends_where(&self, google_user_id: &str, keep_row: impl Fn(&UserFriend) -> bool) -> Vec<String> {
+        self.friendships.iter()
+            .filter(|row| row.user_google_uid == google_user_id && keep_row(row))
+            .filter(|row| {
+                self.friendships.iter()
+                    .any(|row_back| row_back.user_google_uid == row.friend_google_uid && row_back.friend_google_uid == google_user_id && keep_row(row_back))
+            })
+            .map(|row| row.friend_google_uid.to_owned())
+            .collect()
+    }
+
+    fn user_by_uid(&self, google_user_id: &str) -> Option<&Usermaster> {
+        self.users.iter().find(|user| user.uid == google_user_id)
+    }
+
+    fn views_of<'a>(&'a self, google_user_id: &str) -> impl Iterator<Item = &'a NewVideoView> + 'a {
+        let user_id = self.user_by_uid(google_user_id).map(|user| user.id);
+        self.views.iter().filter(move |view| Some(view.user_id) == user_id)
+    }
+
+    // Distinct videos the user has watched
+    fn watched_video_ids(&self, google_user_id: &str) -> HashSet<i64> {
+        let user_id = self.user_by_uid(google_user_id).map(|user| user.id);
+        self.user_videos.iter()
+            .filter(|user_video| Some(user_video.user_id) == user_id)
+            .map(|user_video| user_video.video_id)
+            .collect()
+    }
+
+    fn video(&self, video_id: i64) -> Option<&Video> {
+        self.videos.iter().find(|video| video.id == video_id)
+    }
+
+    fn top_groups(&self, google_user_id: &str, group_of: impl Fn(&Video) -> Option<(&str, Option<&str>)>) -> Vec<TopWatchGroup> {
+        let mut groups: BTreeMap<&str, (Option<&str>, i64)> = BTreeMap::new();
+        let grouped_views = self.views_of(google_user_id)
+            .filter_map(|view| self.video(view.video_id))
+            .filter_map(group_of);
+        for (group_id, group_title) in grouped_views {
+            let group = groups.entry(group_id).or_insert((None, 0));
+            group.0 = group.0.max(group_title);
+            group.1 += 1;
+        }
+
+        groups.into_iter()
+            .map(|(group_id, (group_title, video_count))| TopWatchGroup {
+                group_id: group_id.to_owned(),
+                group_title: group_title.map(str::to_owned),
+                video_count,
+            })
+            .collect()
+    }
+}
+
+fn public_profile(user: &Usermaster) -> PublicProfile {
+    PublicProfile {
+        uid: user.uid.to_owned(),
+        full_name: user.full_name.to_owned(),
+        image_url: user.image_url.to_owned(),
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> MemoryRepository {
+        MemoryRepository::default()
+    }
+}
+
+impl UserRepository for MemoryRepository {
+    fn find_user(&self, google_user_id: &str) -> QueryResult<Option<Usermaster>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.uid == google_user_id).cloned())
+    }
+
+    fn find_users(&self, google_user_ids: &[&str]) -> QueryResult<Vec<Usermaster>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().filter(|user| google_user_ids.contains(&user.uid.as_str())).cloned().collect())
+    }
+
+    fn create_user(&self, new_user: &NewUser) -> QueryResult<Usermaster> {
+        let mut data = self.data.lock().unwrap();
+        let user = Usermaster {
+            id: data.next_id(),
+            uid: new_user.uid.to_string(),
+            provider: new_user.provider.to_string(),
+            full_name: new_user.full_name.to_string(),
+            image_url: new_user.image_url.to_string(),
+            created_at: new_user.created_at,
+            updated_at: None,
+            is_discoverable: true,
+            last_seen_at: None,
+            last_video_id: None,
+        };
+        data.users.push(user.clone());
+        Ok(user)
+    }
+
+    fn update_profile(&self, google_user_id: &str, full_name: &str, image_url: &str, updated_at: NaiveDateTime) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(user) = data.users.iter_mut().find(|user| user.uid == google_user_id) {
+            user.full_name = full_name.to_string();
+            user.image_url = image_url.to_string();
+            user.updated_at = Some(updated_at);
+        }
+        Ok(())
+    }
+
+    fn record_last_seen(&self, google_user_id: &str, seen_at: NaiveDateTime) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(user) = data.users.iter_mut().find(|user| user.uid == google_user_id) {
+            user.last_seen_at = Some(seen_at);
+        }
+        Ok(())
+    }
+
+    fn set_discoverable(&self, google_user_id: &str, is_discoverable: bool, updated_at: NaiveDateTime) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(user) = data.users.iter_mut().find(|user| user.uid == google_user_id) {
+            user.is_discoverable = is_discoverable;
+            user.updated_at = Some(updated_at);
+        }
+        Ok(())
+    }
+
+    // Matches on lowercased names only, without the accent folding and similarity match Postgres does
+    fn search_users(&self, searcher_google_user_id: &str, query: &str, limit: i64) -> QueryResult<Vec<PublicProfile>> {
+        let data = self.data.lock().unwrap();
+        let query = query.to_lowercase();
+        let word_query = format!(" {}", query);
+
+        let mut found: Vec<(bool, &Usermaster)> = data.users.iter()
+            .filter(|user| user.uid != searcher_google_user_id)
+            .filter(|user| {
+                let mut rows = data.rows_between(searcher_google_user_id, &user.uid).peekable();
+                let is_known = rows.peek().is_some();
+                (user.is_discoverable || is_known) && !rows.any(|row| row.is_friend_blocked)
+            })
+            .filter_map(|user| {
+                let name = user.full_name.to_lowercase();
+                let is_prefix = name.starts_with(&query);
+                (is_prefix || name.contains(&word_query)).then_some((is_prefix, user))
+            })
+            .collect();
+        found.sort_by(|(is_prefix, user), (other_is_prefix, other_user)| {
+            other_is_prefix.cmp(is_prefix).then_with(|| user.full_name.cmp(&other_user.full_name))
+        });
+
+        Ok(found.into_iter()
+            .take(limit as usize)
+            .map(|(_, user)| public_profile(user))
+            .collect())
+    }
+}
+
+impl FriendshipRepository for MemoryRepository {
+    fn friends_of(&self, google_user_id: &str) -> QueryResult<Vec<(UserFriend, Option<UserFriend>, Usermaster)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.friendships.iter()
+            .filter(|row| row.user_google_uid == google_user_id)
+            .filter_map(|row| {
+                let row_back = data.friendships.iter()
+                    .find(|row_back| row_back.user_google_uid == row.friend_google_uid && row_back.friend_google_uid == google_user_id)
+                    .cloned();
+                data.users.iter()
+                    .find(|user| user.uid == row.friend_google_uid)
+                    .map(|friend| (row.clone(), row_back, friend.clone()))
+            })
+            .collect())
+    }
+
+    fn find_friendship(&self, google_user_id: &str, friend_google_user_id: &str) -> QueryResult<Option<UserFriend>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.friendships.iter()
+            .find(|row| row.user_google_uid == google_user_id && row.friend_google_uid == friend_google_user_id)
+            .cloned())
+    }
+
+    fn create_friendship(&self, new_friendship: &NewUserFriend) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let row = UserFriend {
+            id: data.next_id(),
+            user_google_uid: new_friendship.user_google_uid.to_string(),
+            friend_google_uid: new_friendship.friend_google_uid.to_string(),
+            is_friend_excluded: new_friendship.is_friend_excluded,
+            created_at: new_friendship.created_at,
+            updated_at: None,
+            is_friend_blocked: false,
+        };
+        data.friendships.push(row);
+        Ok(())
+    }
+
+    fn set_excluded(&self, google_user_id: &str, friend_google_user_id: &str, is_excluded: bool) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(row) = data.friendship_mut(google_user_id, friend_google_user_id) {
+            row.is_friend_excluded = is_excluded;
+        }
+        Ok(())
+    }
+
+    fn set_blocked(&self, google_user_id: &str, friend_google_user_id: &str, is_blocked: bool, updated_at: NaiveDateTime) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(row) = data.friendship_mut(google_user_id, friend_google_user_id) {
+            row.is_friend_blocked = is_blocked;
+            row.updated_at = Some(updated_at);
+        }
+        Ok(())
+    }
+
+    fn mutual_friends(&self, google_user_id: &str, other_google_user_id: &str) -> QueryResult<Vec<PublicProfile>> {
+        let data = self.data.lock().unwrap();
+        let is_unblocked = |row: &UserFriend| !row.is_friend_blocked;
+        let their_friends = data.friends_where(other_google_user_id, is_unblocked);
+
+        let mut mutual_friends: Vec<&Usermaster> = data.friends_where(google_user_id, is_unblocked).iter()
+            .filter(|friend_google_user_id| their_friends.contains(friend_google_user_id))
+            .filter_map(|friend_google_user_id| data.user_by_uid(friend_google_user_id))
+            .collect();
+        mutual_friends.sort_by(|user, other_user| user.full_name.cmp(&other_user.full_name));
+
+        Ok(mutual_friends.into_iter().map(public_profile).collect())
+    }
+
+    fn friend_suggestions(&self, google_user_id: &str, limit: i64, recent_video_count: i64) -> QueryResult<Vec<FriendSuggestion>> {
+        let data = self.data.lock().unwrap();
+
+        let mut mutual_friend_counts: HashMap<&str, i64> = HashMap::new();
+        for friend_google_user_id in data.friends_where(google_user_id, |row| !row.is_friend_blocked) {
+            let friends_of_friend = data.friendships.iter()
+                .filter(|row| row.user_google_uid == friend_google_user_id && !row.is_friend_blocked && row.friend_google_uid != google_user_id);
+            for row in friends_of_friend {
+                *mutual_friend_counts.entry(row.friend_google_uid.as_str()).or_default() += 1;
+            }
+        }
+
+        let mut shared_video_counts: HashMap<&str, i64> = HashMap::new();
+        if let Some(user) = data.user_by_uid(google_user_id) {
+            let mut my_user_videos: Vec<&UserVideo> = data.user_videos.iter()
+                .filter(|user_video| user_video.user_id == user.id)
+                .collect();
+            my_user_videos.sort_by_key(|user_video| Reverse(user_video.created_at));
+            let my_videos: HashSet<i64> = my_user_videos.into_iter()
+                .take(recent_video_count as usize)
+                .map(|user_video| user_video.video_id)
+                .collect();
+
+            let other_viewers = data.user_videos.iter()
+                .filter(|user_video| user_video.user_id != user.id && my_videos.contains(&user_video.video_id))
+                .filter_map(|user_video| data.users.iter().find(|viewer| viewer.id == user_video.user_id));
+            for viewer in other_viewers {
+                *shared_video_counts.entry(viewer.uid.as_str()).or_default() += 1;
+            }
+        }
+
+        let candidates: HashSet<&str> = mutual_friend_counts.keys().chain(shared_video_counts.keys()).copied().collect();
+        let mut suggestions: Vec<FriendSuggestion> = candidates.into_iter()
+            .filter(|candidate| data.rows_between(google_user_id, candidate).next().is_none())
+            .filter_map(|candidate| data.user_by_uid(candidate))
+            .filter(|user| user.is_discoverable)
+            .map(|user| FriendSuggestion {
+                uid: user.uid.to_owned(),
+                full_name: user.full_name.to_owned(),
+                image_url: user.image_url.to_owned(),
+                mutual_friend_count: mutual_friend_counts.get(user.uid.as_str()).copied().unwrap_or(0),
+                shared_video_count: shared_video_counts.get(user.uid.as_str()).copied().unwrap_or(0),
+            })
+            .collect();
+        suggestions.sort_by(|suggestion, other| {
+            other.mutual_friend_count.cmp(&suggestion.mutual_friend_count)
+                .then_with(|| other.shared_video_count.cmp(&suggestion.shared_video_count))
+                .then_with(|| suggestion.full_name.cmp(&other.full_name))
+        });
+        suggestions.truncate(limit as usize);
+
+        Ok(suggestions)
+    }
+}
+
+impl VideoRepository for MemoryRepository {
+    fn find_video(&self, platform: &str, external_id: &str) -> QueryResult<Option<Video>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.videos.iter()
+            .find(|video| video.platform == platform && video.external_id == external_id)
+            .cloned())
+    }
+
+    fn find_videos(&self, video_ids: &[i64]) -> QueryResult<Vec<Video>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.videos.iter().filter(|video| video_ids.contains(&video.id)).cloned().collect())
+    }
+
+    fn create_video(&self, new_video: &NewVideo) -> QueryResult<Video> {
+        let mut data = self.data.lock().unwrap();
+        let video = Video {
+            id: data.next_id(),
+            video_url: new_video.video_url.to_string(),
+            external_id: new_video.external_id.to_string(),
+            video_title: new_video.video_title.to_string(),
+            created_at: new_video.created_at,
+            updated_at: None,
+            channel_id: new_video.channel_id.map(str::to_string),
+            channel_title: new_video.channel_title.map(str::to_string),
+            published_at: new_video.published_at,
+            category_id: new_video.category_id.map(str::to_string),
+            thumbnail_default_url: new_video.thumbnail_default_url.map(str::to_string),
+            thumbnail_medium_url: new_video.thumbnail_medium_url.map(str::to_string),
+            thumbnail_high_url: new_video.thumbnail_high_url.map(str::to_string),
+            thumbnail_maxres_url: new_video.thumbnail_maxres_url.map(str::to_string),
+            duration_seconds: new_video.duration_seconds,
+            is_live_broadcast: new_video.is_live_broadcast,
+            platform: new_video.platform.to_string(),
+            view_count: 0,
+            unique_viewer_count: 0,
+        };
+        data.videos.push(video.clone());
+        Ok(video)
+    }
+
+    fn update_video_metadata(&self, video_id: i64, video_metadata_update: &VideoMetadataUpdate) -> QueryResult<Video> {
+        let mut data = self.data.lock().unwrap();
+        let video = data.videos.iter_mut()
+            .find(|video| video.id == video_id)
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        video.video_title = video_metadata_update.video_title.to_string();
+        video.channel_id = video_metadata_update.channel_id.map(str::to_string);
+        video.channel_title = video_metadata_update.channel_title.map(str::to_string);
+        video.published_at = video_metadata_update.published_at;
+        video.category_id = video_metadata_update.category_id.map(str::to_string);
+        video.thumbnail_default_url = video_metadata_update.thumbnail_default_url.map(str::to_string);
+        video.thumbnail_medium_url = video_metadata_update.thumbnail_medium_url.map(str::to_string);
+        video.thumbnail_high_url = video_metadata_update.thumbnail_high_url.map(str::to_string);
+        video.thumbnail_maxres_url = video_metadata_update.thumbnail_maxres_url.map(str::to_string);
+        video.duration_seconds = video_metadata_update.duration_seconds;
+        video.is_live_broadcast = video_metadata_update.is_live_broadcast;
+        video.updated_at = Some(video_metadata_update.updated_at);
+        Ok(video.clone())
+    }
+
+    fn record_watch(&self, user_id: i64, video_id: i64, watched_at: NaiveDateTime) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.views.push(NewVideoView { video_id, user_id, created_at: watched_at });
+
+        let is_new_viewer = !data.user_videos.iter()
+            .any(|user_video| user_video.user_id == user_id && user_video.video_id == video_id);
+        if is_new_viewer {
+            let user_video = UserVideo { id: data.next_id(), user_id, video_id, created_at: watched_at, updated_at: None };
+            data.user_videos.push(user_video);
+        }
+
+        let video = data.videos.iter_mut()
+            .find(|video| video.id == video_id)
+            .ok_or(diesel::result::Error::NotFound)?;
+        video.view_count += 1;
+        if is_new_viewer {
+            video.unique_viewer_count += 1;
+        }
+
+        if let Some(user) = data.users.iter_mut().find(|user| user.id == user_id) {
+            user.last_seen_at = Some(watched_at);
+            user.last_video_id = Some(video_id);
+        }
+        Ok(())
+    }
+
+    fn watch_history(&self, user_id: i64, limit: i64, offset: i64) -> QueryResult<Vec<(UserVideo, Video)>> {
+        let data = self.data.lock().unwrap();
+        let mut watched_videos: Vec<(UserVideo, Video)> = data.user_videos.iter()
+            .filter(|user_video| user_video.user_id == user_id)
+            .filter_map(|user_video| {
+                data.videos.iter()
+                    .find(|video| video.id == user_video.video_id)
+                    .map(|video| (user_video.clone(), video.clone()))
+            })
+            .collect();
+        watched_videos.sort_by_key(|(user_video, _)| Reverse(user_video.created_at));
+
+        Ok(watched_videos.into_iter().skip(offset as usize).take(limit as usize).collect())
+    }
+
+    fn find_playlist(&self, platform: &str, external_id: &str) -> QueryResult<Option<Playlist>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.playlists.iter()
+            .find(|playlist| playlist.platform == platform && playlist.external_id == external_id)
+            .cloned())
+    }
+
+    fn create_playlist(&self, new_playlist: &NewPlaylist) -> QueryResult<Playlist> {
+        let mut data = self.data.lock().unwrap();
+        let playlist = Playlist {
+            id: data.next_id(),
+            platform: new_playlist.platform.to_string(),
+            external_id: new_playlist.external_id.to_string(),
+            title: new_playlist.title.to_string(),
+            channel_id: new_playlist.channel_id.map(str::to_string),
+            channel_title: new_playlist.channel_title.map(str::to_string),
+            item_count: new_playlist.item_count,
+            thumbnail_url: new_playlist.thumbnail_url.map(str::to_string),
+            created_at: new_playlist.created_at,
+            updated_at: None,
+        };
+        data.playlists.push(playlist.clone());
+        Ok(playlist)
+    }
+
+    fn update_playlist_metadata(&self, playlist_id: i64, playlist_metadata_update: &PlaylistMetadataUpdate) -> QueryResult<Playlist> {
+        let mut data = self.data.lock().unwrap();
+        let playlist = data.playlists.iter_mut()
+            .find(|playlist| playlist.id == playlist_id)
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        playlist.title = playlist_metadata_update.title.to_string();
+        playlist.channel_id = playlist_metadata_update.channel_id.map(str::to_string);
+        playlist.channel_title = playlist_metadata_update.channel_title.map(str::to_string);
+        playlist.item_count = playlist_metadata_update.item_count;
+        playlist.thumbnail_url = playlist_metadata_update.thumbnail_url.map(str::to_string);
+        playlist.updated_at = Some(playlist_metadata_update.updated_at);
+        Ok(playlist.clone())
+    }
+
+    fn playlist_position(&self, playlist_id: i64, platform: &str, external_id: &str) -> QueryResult<Option<i32>> {
+        let data = self.data.lock().unwrap();
+        let video_id = match data.videos.iter().find(|video| video.platform == platform && video.external_id == external_id) {
+            Some(video) => video.id,
+            None => return Ok(None)
+        };
+        Ok(data.playlist_videos.iter()
+            .find(|playlist_video| playlist_video.playlist_id == playlist_id && playlist_video.video_id == video_id)
+            .and_then(|playlist_video| playlist_video.position))
+    }
+
+    fn save_playlist_video(&self, new_playlist_video: &NewPlaylistVideo) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let existing_playlist_video = data.playlist_videos.iter_mut()
+            .find(|playlist_video| playlist_video.playlist_id == new_playlist_video.playlist_id && playlist_video.video_id == new_playlist_video.video_id);
+
+        match existing_playlist_video {
+            Some(playlist_video) => {
+                playlist_video.position = new_playlist_video.position;
+                playlist_video.updated_at = Some(new_playlist_video.created_at);
+            },
+            None => {
+                let playlist_video = PlaylistVideo {
+                    id: data.next_id(),
+                    playlist_id: new_playlist_video.playlist_id,
+                    video_id: new_playlist_video.video_id,
+                    position: new_playlist_video.position,
+                    created_at: new_playlist_video.created_at,
+                    updated_at: None,
+                };
+                data.playlist_videos.push(playlist_video);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl InboxRepository for MemoryRepository {
+    fn store_notification(&self, new_notification: &NewNotification) -> QueryResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let notification = Notification {
+            id: data.next_id(),
+            recipient_google_uid: new_notification.recipient_google_uid.to_string(),
+            action: new_notification.action.to_string(),
+            payload: new_notification.payload.to_string(),
+            delivered_at: None,
+            read_at: None,
+            created_at: new_notification.created_at,
+            updated_at: None,
+        };
+        data.notifications.push(notification);
+        Ok(())
+    }
+
+    fn take_pending_notifications(&self, google_user_id: &str, limit: i64, delivered_at: NaiveDateTime) -> QueryResult<Vec<Notification>> {
+        let mut data = self.data.lock().unwrap();
+        let pending_notifications: Vec<&mut Notification> = data.notifications.iter_mut()
+            .filter(|notification| notification.recipient_google_uid == google_user_id && notification.read_at.is_none())
+            .take(limit as usize)
+            .collect();
+
+        Ok(pending_notifications.into_iter()
+            .map(|notification| {
+                let pending_notification = notification.clone();
+                notification.delivered_at.get_or_insert(delivered_at);
+                pending_notification
+            })
+            .collect())
+    }
+
+    fn deliver_chat_messages(&self, google_user_id: &str, delivered_at: NaiveDateTime) -> QueryResult<Vec<Message>> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data.messages.iter_mut()
+            .filter(|message| message.recipient_google_uid == google_user_id && message.delivered_at.is_none())
+            .map(|message| {
+                message.delivered_at = Some(delivered_at);
+                message.updated_at = Some(delivered_at);
+                message.clone()
+            })
+            .collect())
+    }
+
+    fn mark_notifications_read(&self, google_user_id: &str, notification_ids: Option<&[i64]>, read_at: NaiveDateTime) -> QueryResult<usize> {
+        let mut data = self.data.lock().unwrap();
+        let unread_notifications = data.notifications.iter_mut()
+            .filter(|notification| notification.recipient_google_uid == google_user_id && notification.read_at.is_none())
+            .filter(|notification| notification_ids.is_none_or(|notification_ids| notification_ids.contains(&notification.id)));
+
+        let mut marked_count = 0;
+        for notification in unread_notifications {
+            notification.read_at = Some(read_at);
+            notification.updated_at = Some(read_at);
+            marked_count += 1;
+        }
+        Ok(marked_count)
+    }
+}
+
+impl ChatRepository for MemoryRepository {
+    fn create_message(&self, new_message: &NewMessage) -> QueryResult<Message> {
+        let mut data = self.data.lock().unwrap();
+        let message = Message {
+            id: data.next_id(),
+            sender_google_uid: new_message.sender_google_uid.to_string(),
+            recipient_google_uid: new_message.recipient_google_uid.to_string(),
+            body: new_message.body.to_string(),
+            delivered_at: None,
+            read_at: None,
+            created_at: new_message.created_at,
+            updated_at: None,
+        };
+        data.messages.push(message.clone());
+        Ok(message)
+    }
+
+    fn mark_message_delivered(&self, message_id: i64, delivered_at: NaiveDateTime) -> QueryResult<Message> {
+        let mut data = self.data.lock().unwrap();
+        let message = data.messages.iter_mut()
+            .find(|message| message.id == message_id)
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        message.delivered_at = Some(delivered_at);
+        Ok(message.clone())
+    }
+
+    fn mark_messages_read(&self, recipient_google_user_id: &str, sender_google_user_id: &str, up_to_message_id: i64, read_at: NaiveDateTime) -> QueryResult<Vec<Message>> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data.messages.iter_mut()
+            .filter(|message| {
+                message.recipient_google_uid == recipient_google_user_id
+                    && message.sender_google_uid == sender_google_user_id
+                    && message.id <= up_to_message_id
+                    && message.read_at.is_none()
+            })
+            .map(|message| {
+                message.delivered_at.get_or_insert(read_at);
+                message.read_at = Some(read_at);
+                message.updated_at = Some(read_at);
+                message.clone()
+            })
+            .collect())
+    }
+}
+
+impl ReactionRepository for MemoryRepository {
+    fn create_reaction(&self, new_reaction: &NewVideoReaction) -> QueryResult<VideoReaction> {
+        let mut data = self.data.lock().unwrap();
+        let reaction = VideoReaction {
+            id: data.next_id(),
+            video_id: new_reaction.video_id,
+            watcher_google_uid: new_reaction.watcher_google_uid.to_string(),
+            reactor_google_uid: new_reaction.reactor_google_uid.to_string(),
+            emoji: new_reaction.emoji.map(str::to_string),
+            comment: new_reaction.comment.map(str::to_string),
+            created_at: new_reaction.created_at,
+            updated_at: None,
+        };
+        data.reactions.push(reaction.clone());
+        Ok(reaction)
+    }
+
+    // Kept in insertion order, which is oldest first
+    fn reactions_to(&self, watcher_google_user_id: &str, video_ids: &[i64]) -> QueryResult<Vec<(VideoReaction, Usermaster)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.reactions.iter()
+            .filter(|reaction| reaction.watcher_google_uid == watcher_google_user_id && video_ids.contains(&reaction.video_id))
+            .filter_map(|reaction| {
+                data.users.iter()
+                    .find(|user| user.uid == reaction.reactor_google_uid)
+                    .map(|reactor| (reaction.clone(), reactor.clone()))
+            })
+            .collect())
+    }
+}
+
+impl ShareRepository for MemoryRepository {
+    fn create_share(&self, new_video_share: &NewVideoShare) -> QueryResult<VideoShare> {
+        let mut data = self.data.lock().unwrap();
+        let video_share = VideoShare {
+            id: data.next_id(),
+            video_id: new_video_share.video_id,
+            sender_google_uid: new_video_share.sender_google_uid.to_string(),
+            recipient_google_uid: new_video_share.recipient_google_uid.to_string(),
+            note: new_video_share.note.map(str::to_string),
+            created_at: new_video_share.created_at,
+            updated_at: None,
+        };
+        data.shares.push(video_share.clone());
+        Ok(video_share)
+    }
+}
+
+impl InviteRepository for MemoryRepository {
+    fn create_invite_code(&self, new_invite_code: &NewInviteCode) -> QueryResult<InviteCode> {
+        let mut data = self.data.lock().unwrap();
+        let invite_code = InviteCode {
+            id: data.next_id(),
+            code: new_invite_code.code.to_string(),
+            inviter_google_uid: new_invite_code.inviter_google_uid.to_string(),
+            max_uses: new_invite_code.max_uses,
+            use_count: 0,
+            expires_at: new_invite_code.expires_at,
+            created_at: new_invite_code.created_at,
+            updated_at: None,
+        };
+        data.invite_codes.push(invite_code.clone());
+        Ok(invite_code)
+    }
+
+    fn redeem_invite_code(&self, code: &str, invitee_google_user_id: &str, redeemed_at: NaiveDateTime) -> QueryResult<Option<InviteCode>> {
+        let mut data = self.data.lock().unwrap();
+        let claimed_code = data.invite_codes.iter_mut()
+            .find(|invite_code| {
+                invite_code.code == code
+                    && invite_code.max_uses.is_none_or(|max_uses| invite_code.use_count < max_uses)
+                    && invite_code.expires_at.is_none_or(|expires_at| expires_at > redeemed_at)
+            })
+            .map(|invite_code| {
+                invite_code.use_count += 1;
+                invite_code.updated_at = Some(redeemed_at);
+                invite_code.clone()
+            });
+
+        if let Some(ref claimed_code) = claimed_code {
+            data.invite_redemptions.push((claimed_code.id, invitee_google_user_id.to_string()));
+        }
+        Ok(claimed_code)
+    }
+}
+
+fn bucket_counts(days: impl Iterator<Item = NaiveDate>) -> Vec<WatchCountBucket> {
+    let mut counts: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for day in days {
+        *counts.entry(day).or_default() += 1;
+    }
+    counts.into_iter().map(|(bucket, video_count)| WatchCountBucket { bucket, video_count }).collect()
+}
+
+impl StatsRepository for MemoryRepository {
+    fn watch_days(&self, google_user_id: &str, utc_offset_minutes: i32) -> QueryResult<Vec<WatchCountBucket>> {
+        let data = self.data.lock().unwrap();
+        let offset = Duration::minutes(utc_offset_minutes as i64);
+        Ok(bucket_counts(data.views_of(google_user_id).map(|view| (view.created_at + offset).date())))
+    }
+
+    fn watch_weeks(&self, google_user_id: &str, utc_offset_minutes: i32, since: NaiveDateTime) -> QueryResult<Vec<WatchCountBucket>> {
+        let data = self.data.lock().unwrap();
+        let offset = Duration::minutes(utc_offset_minutes as i64);
+        let weeks = data.views_of(google_user_id)
+            .filter(|view| view.created_at >= since)
+            .map(|view| {
+                let day = (view.created_at + offset).date();
+                day - Duration::days(day.weekday().num_days_from_monday() as i64)
+            });
+        Ok(bucket_counts(weeks))
+    }
+
+    fn watch_time_totals(&self, google_user_id: &str) -> QueryResult<WatchTimeTotals> {
+        let data = self.data.lock().unwrap();
+        let durations: Vec<Option<i64>> = data.views_of(google_user_id)
+            .filter_map(|view| data.video(view.video_id))
+            .map(|video| video.duration_seconds)
+            .collect();
+
+        Ok(WatchTimeTotals {
+            video_count: durations.len() as i64,
+            watch_seconds: durations.iter().flatten().sum(),
+            unknown_duration_count: durations.iter().filter(|seconds| seconds.is_none()).count() as i64,
+        })
+    }
+
+    fn top_channels(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>> {
+        let data = self.data.lock().unwrap();
+        let mut top_channels = data.top_groups(google_user_id, |video| {
+            video.channel_id.as_deref().map(|channel_id| (channel_id, video.channel_title.as_deref()))
+        });
+        top_channels.sort_by(|group, other| other.video_count.cmp(&group.video_count).then_with(|| group.group_title.cmp(&other.group_title)));
+        top_channels.truncate(limit as usize);
+        Ok(top_channels)
+    }
+
+    fn top_categories(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>> {
+        let data = self.data.lock().unwrap();
+        let mut top_categories = data.top_groups(google_user_id, |video| {
+            video.category_id.as_deref().map(|category_id| (category_id, None))
+        });
+        top_categories.sort_by(|group, other| other.video_count.cmp(&group.video_count).then_with(|| group.group_id.cmp(&other.group_id)));
+        top_categories.truncate(limit as usize);
+        Ok(top_categories)
+    }
+
+    fn taste_overlaps(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TasteOverlap>> {
+        let data = self.data.lock().unwrap();
+        let my_videos = data.watched_video_ids(google_user_id);
+
+        let mut taste_overlaps: Vec<TasteOverlap> = data.friends_where(google_user_id, |row| !row.is_friend_excluded && !row.is_friend_blocked).iter()
+            .filter_map(|friend_google_user_id| data.user_by_uid(friend_google_user_id))
+            .filter_map(|friend| {
+                let their_videos = data.watched_video_ids(&friend.uid);
+                let shared_video_count = their_videos.intersection(&my_videos).count();
+                let watched_by_either = their_videos.len() + my_videos.len() - shared_video_count;
+                (shared_video_count > 0).then(|| TasteOverlap {
+                    uid: friend.uid.to_owned(),
+                    full_name: friend.full_name.to_owned(),
+                    image_url: friend.image_url.to_owned(),
+                    shared_video_count: shared_video_count as i64,
+                    similarity: shared_video_count as f64 / watched_by_either as f64,
+                })
+            })
+            .collect();
+        taste_overlaps.sort_by(|overlap, other| {
+            other.similarity.total_cmp(&overlap.similarity).then_with(|| other.shared_video_count.cmp(&overlap.shared_video_count))
+        });
+        taste_overlaps.truncate(limit as usize);
+        Ok(taste_overlaps)
+    }
+}
+
+impl DiscoveryRepository for MemoryRepository {
+    fn friends_trending(&self, google_user_id: &str, since: NaiveDateTime, limit: i64) -> QueryResult<Vec<TrendingVideo>> {
+        let data = self.data.lock().unwrap();
+
+        let mut friend_views: HashMap<i64, Vec<(NaiveDateTime, String)>> = HashMap::new();
+        for friend_google_user_id in data.friends_where(google_user_id, |row| !row.is_friend_excluded && !row.is_friend_blocked) {
+            let friend_id = data.user_by_uid(&friend_google_user_id).map(|friend| friend.id);
+            let recent_user_videos = data.user_videos.iter()
+                .filter(|user_video| Some(user_video.user_id) == friend_id && user_video.created_at >= since);
+            for user_video in recent_user_videos {
+                friend_views.entry(user_video.video_id).or_default().push((user_video.created_at, friend_google_user_id.to_owned()));
+            }
+        }
+
+        let mut trending_videos: Vec<TrendingVideo> = friend_views.into_iter()
+            .map(|(video_id, mut viewers)| {
+                viewers.sort_by_key(|(watched_at, _)| Reverse(*watched_at));
+                TrendingVideo {
+                    video_id,
+                    viewer_count: viewers.len() as i64,
+                    last_watched_at: viewers[0].0,
+                    viewer_google_uids: viewers.into_iter().map(|(_, viewer)| viewer).collect(),
+                }
+            })
+            .collect();
+        trending_videos.sort_by(|trending, other| {
+            other.viewer_count.cmp(&trending.viewer_count).then_with(|| other.last_watched_at.cmp(&trending.last_watched_at))
+        });
+        trending_videos.truncate(limit as usize);
+        Ok(trending_videos)
+    }
+
+    fn recommendations(&self, google_user_id: &str, seed_depth: i64, friend_viewer_weight: f64, limit: i64, offset: i64) -> QueryResult<Vec<Recommendation>> {
+        let data = self.data.lock().unwrap();
+        let user_id = data.user_by_uid(google_user_id).map(|user| user.id);
+
+        let mut my_history: Vec<&UserVideo> = data.user_videos.iter()
+            .filter(|user_video| Some(user_video.user_id) == user_id)
+            .collect();
+        my_history.sort_by_key(|user_video| Reverse(user_video.created_at));
+        let watched_video_ids: HashSet<i64> = my_history.iter().map(|user_video| user_video.video_id).collect();
+        let seed_video_ids: HashSet<i64> = my_history.iter().take(seed_depth as usize).map(|user_video| user_video.video_id).collect();
+
+        let mut candidates: HashMap<i64, f64> = HashMap::new();
+        let neighbours = data.similarities.iter()
+            .filter(|similarity| seed_video_ids.contains(&similarity.video_id) && !watched_video_ids.contains(&similarity.similar_video_id));
+        for similarity in neighbours {
+            *candidates.entry(similarity.similar_video_id).or_default() += similarity.score;
+        }
+
+        let friend_ids: HashSet<i64> = data.friends_where(google_user_id, |row| !row.is_friend_excluded && !row.is_friend_blocked).iter()
+            .filter_map(|friend_google_user_id| data.user_by_uid(friend_google_user_id))
+            .map(|friend| friend.id)
+            .collect();
+
+        let mut recommendations: Vec<Recommendation> = candidates.into_iter()
+            .map(|(video_id, similarity_score)| {
+                let friend_viewer_count = data.user_videos.iter()
+                    .filter(|user_video| user_video.video_id == video_id && friend_ids.contains(&user_video.user_id))
+                    .count() as i64;
+                Recommendation {
+                    video_id,
+                    score: similarity_score * (1.0 + friend_viewer_weight * friend_viewer_count as f64),
+                    friend_viewer_count,
+                }
+            })
+            .collect();
+        recommendations.sort_by(|recommendation, other| {
+            other.score.total_cmp(&recommendation.score).then_with(|| other.video_id.cmp(&recommendation.video_id))
+        });
+
+        Ok(recommendations.into_iter().skip(offset as usize).take(limit as usize).collect())
+    }
+
+    fn rebuild_video_similarities(&self, history_depth: i64, neighbours_per_video: i64, _rebuilt_at: NaiveDateTime) -> QueryResult<usize> {
+        let mut data = self.data.lock().unwrap();
+
+        let mut histories: HashMap<i64, Vec<&UserVideo>> = HashMap::new();
+        for user_video in data.user_videos.iter() {
+            histories.entry(user_video.user_id).or_default().push(user_video);
+        }
+        let recent_histories: Vec<Vec<i64>> = histories.into_values()
+            .map(|mut history| {
+                history.sort_by_key(|user_video| Reverse(user_video.created_at));
+                history.into_iter().take(history_depth as usize).map(|user_video| user_video.video_id).collect()
+            })
+            .collect();
+
+        let mut viewer_counts: HashMap<i64, i64> = HashMap::new();
+        let mut co_watch_counts: HashMap<(i64, i64), i64> = HashMap::new();
+        for history in recent_histories.iter() {
+            for video_id in history.iter() {
+                *viewer_counts.entry(*video_id).or_default() += 1;
+                for similar_video_id in history.iter().filter(|similar_video_id| *similar_video_id != video_id) {
+                    *co_watch_counts.entry((*video_id, *similar_video_id)).or_default() += 1;
+                }
+            }
+        }
+
+        let mut neighbours: HashMap<i64, Vec<(i64, i64, f64)>> = HashMap::new();
+        for ((video_id, similar_video_id), co_watch_count) in co_watch_counts {
+            let score = co_watch_count as f64 / ((viewer_counts[&video_id] * viewer_counts[&similar_video_id]) as f64).sqrt();
+            neighbours.entry(video_id).or_default().push((similar_video_id, co_watch_count, score));
+        }
+
+        data.similarities = neighbours.into_iter()
+            .flat_map(|(video_id, mut similar_videos)| {
+                similar_videos.sort_by(|(similar_video_id, co_watch_count, score), (other_id, other_count, other_score)| {
+                    other_score.total_cmp(score).then_with(|| other_count.cmp(co_watch_count)).then_with(|| similar_video_id.cmp(other_id))
+                });
+                similar_videos.into_iter()
+                    .take(neighbours_per_video as usize)
+                    .map(move |(similar_video_id, _, score)| VideoSimilarity { video_id, similar_video_id, score })
+            })
+            .collect();
+        Ok(data.similarities.len())
+    }
+
+    fn leaderboard(&self, since: Option<NaiveDateTime>, limit: i64, offset: i64) -> QueryResult<Vec<VideoPopularity>> {
+        let data = self.data.lock().unwrap();
+        let mut leaderboard: Vec<VideoPopularity> = match since {
+            Some(since) => {
+                let mut viewers: HashMap<i64, (i64, HashSet<i64>)> = HashMap::new();
+                for view in data.views.iter().filter(|view| view.created_at >= since) {
+                    let video_viewers = viewers.entry(view.video_id).or_default();
+                    video_viewers.0 += 1;
+                    video_viewers.1.insert(view.user_id);
+                }
+                viewers.into_iter()
+                    .map(|(video_id, (view_count, viewer_ids))| VideoPopularity { video_id, view_count, unique_viewer_count: viewer_ids.len() as i64 })
+                    .collect()
+            },
+            None => data.videos.iter()
+                .filter(|video| video.view_count > 0)
+                .map(|video| VideoPopularity { video_id: video.id, view_count: video.view_count, unique_viewer_count: video.unique_viewer_count })
+                .collect()
+        };
+        leaderboard.sort_by_key(|popularity| Reverse((popularity.view_count, popularity.unique_viewer_count, popularity.video_id)));
+
+        Ok(leaderboard.into_iter().skip(offset as usize).take(limit as usize).collect())
+    }
+
+    fn rebuild_video_counters(&self) -> QueryResult<usize> {
+        let mut data = self.data.lock().unwrap();
+        let mut counts: HashMap<i64, (i64, HashSet<i64>)> = HashMap::new();
+        for view in data.views.iter() {
+            let video_counts = counts.entry(view.video_id).or_default();
+            video_counts.0 += 1;
+            video_counts.1.insert(view.user_id);
+        }
+
+        let mut rebuilt_count = 0;
+        for video in data.videos.iter_mut() {
+            let (view_count, unique_viewer_count) = counts.get(&video.id)
+                .map(|(view_count, viewer_ids)| (*view_count, viewer_ids.len() as i64))
+                .unwrap_or((0, 0));
+            if (video.view_count, video.unique_viewer_count) != (view_count, unique_viewer_count) {
+                video.view_count = view_count;
+                video.unique_viewer_count = unique_viewer_count;
+                rebuilt_count += 1;
+            }
+        }
+        Ok(rebuilt_count)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 19).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn create_user(repository: &MemoryRepository, google_user_id: &str) -> Usermaster {
+        repository.create_user(&NewUser {
+            uid: google_user_id, provider: "google".to_string(), full_name: google_user_id, image_url: "", created_at: at(9),
+        }).unwrap()
+    }
+
+    fn create_video(repository: &MemoryRepository, external_id: &str) -> Video {
+        repository.create_video(&NewVideo {
+            video_url: &format!("https://www.youtube.com/watch?v={}", external_id),
+            external_id,
+            video_title: external_id,
+            channel_id: None,
+            channel_title: None,
+            published_at: None,
+            category_id: None,
+            thumbnail_default_url: None,
+            thumbnail_medium_url: None,
+            thumbnail_high_url: None,
+            thumbnail_maxres_url: None,
+            duration_seconds: Some(213),
+            is_live_broadcast: false,
+            platform: "youtube",
+            created_at: at(9),
+        }).unwrap()
+    }
+
+    #[test]
+    fn watches_move_counters_and_last_video() {
+        let repository = MemoryRepository::new();
+        let user = create_user(&repository, "alice");
+        let video = create_video(&repository, "dQw4w9WgXcQ");
+
+        repository.record_watch(user.id, video.id, at(10)).unwrap();
+        repository.record_watch(user.id, video.id, at(11)).unwrap();
+
+        let video = repository.find_video("youtube", "dQw4w9WgXcQ").unwrap().unwrap();
+        assert_eq!((video.view_count, video.unique_viewer_count), (2, 1));
+
+        let user = repository.find_user("alice").unwrap().unwrap();
+        assert_eq!((user.last_seen_at, user.last_video_id), (Some(at(11)), Some(video.id)));
+    }
+
+    #[test]
+    fn undiscoverable_strangers_are_not_suggested() {
+        let repository = MemoryRepository::new();
+        let [alice, carol] = ["alice", "carol"].map(|google_user_id| create_user(&repository, google_user_id));
+        let video = create_video(&repository, "dQw4w9WgXcQ");
+        repository.record_watch(alice.id, video.id, at(10)).unwrap();
+        repository.record_watch(carol.id, video.id, at(10)).unwrap();
+
+        let suggested = |repository: &MemoryRepository| -> Vec<String> {
+            repository.friend_suggestions("alice", 20, 200).unwrap().into_iter().map(|suggestion| suggestion.uid).collect()
+        };
+        assert_eq!(suggested(&repository), vec!["carol"]);
+
+        repository.set_discoverable("carol", false, at(11)).unwrap();
+        assert!(suggested(&repository).is_empty());
+    }
+
+    #[test]
+    fn co_watched_videos_are_recommended_friends_first() {
+        let repository = MemoryRepository::new();
+        let [alice, bob, carol] = ["alice", "bob", "carol"].map(|google_user_id| create_user(&repository, google_user_id));
+        let [seed, by_friend, by_stranger] = ["seed", "by-friend", "by-stranger"].map(|external_id| create_video(&repository, external_id));
+
+        for (google_user_id, friend_google_user_id) in [("alice", "bob"), ("bob", "alice")] {
+            repository.create_friendship(&NewUserFriend {
+                user_google_uid: google_user_id, friend_google_uid: friend_google_user_id, is_friend_excluded: false, created_at: at(9),
+            }).unwrap();
+        }
+        for (user, video) in [(&alice, &seed), (&bob, &seed), (&bob, &by_friend), (&carol, &seed), (&carol, &by_stranger)] {
+            repository.record_watch(user.id, video.id, at(10)).unwrap();
+        }
+
+        assert_eq!(repository.rebuild_video_similarities(200, 50, at(11)).unwrap(), 4);
+
+        let recommendations = repository.recommendations("alice", 50, 0.5, 10, 0).unwrap();
+        let recommended: Vec<(i64, i64)> = recommendations.iter()
+            .map(|recommendation| (recommendation.video_id, recommendation.friend_viewer_count))
+            .collect();
+        assert_eq!(recommended, vec![(by_friend.id, 1), (by_stranger.id, 0)]);
+        assert!(recommendations[0].score > recommendations[1].score);
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::NaiveDateTime;
+use diesel::QueryResult;
+
+use crate::models::{FriendSuggestion, InviteCode, Message, NewInviteCode, NewMessage, NewNotification, NewPlaylist, NewPlaylistVideo, NewUser, NewUserFriend, NewVideo, NewVideoReaction, NewVideoShare, Notification, Playlist, PlaylistMetadataUpdate, PublicProfile, Recommendation, TasteOverlap, TopWatchGroup, TrendingVideo, UserFriend, Usermaster, UserVideo, Video, VideoMetadataUpdate, VideoPopularity, VideoReaction, VideoShare, WatchCountBucket, WatchTimeTotals};
+
+mod memory;
+mod postgres;
+mod sql;
+
+pub use self::memory::MemoryRepository;
+pub use self::postgres::PgRepository;
+
+
+pub trait UserRepository {
+    fn find_user(&self, google_user_id: &str) -> QueryResult<Option<Usermaster>>;
+    fn find_users(&self, google_user_ids: &[&str]) -> QueryResult<Vec<Usermaster>>;
+    fn create_user(&self, new_user: &NewUser) -> QueryResult<Usermaster>;
+    fn update_profile(&self, google_user_id: &str, full_name: &str, image_url: &str, updated_at: NaiveDateTime) -> QueryResult<()>;
+    fn record_last_seen(&self, google_user_id: &str, seen_at: NaiveDateTime) -> QueryResult<()>;
+    fn set_discoverable(&self, google_user_id: &str, is_discoverable: bool, updated_at: NaiveDateTime) -> QueryResult<()>;
+    // Users whose name or one of its words starts with the query, and with Postgres also names close to it.
+    // Blocked users are left out, and so are undiscoverable ones the searcher has no userfriends row with.
+    fn search_users(&self, searcher_google_user_id: &str, query: &str, limit: i64) -> QueryResult<Vec<PublicProfile>>;
+}
+
+// Friendships are directed rows, a confirmed friendship has one row each way
+pub trait FriendshipRepository {
+    // The user's rows, each with the friend's row back when there is one and the friend's user
+    fn friends_of(&self, google_user_id: &str) -> QueryResult<Vec<(UserFriend, Option<UserFriend>, Usermaster)>>;
+    fn find_friendship(&self, google_user_id: &str, friend_google_user_id: &str) -> QueryResult<Option<UserFriend>>;
+    fn create_friendship(&self, new_friendship: &NewUserFriend) -> QueryResult<()>;
+    fn set_excluded(&self, google_user_id: &str, friend_google_user_id: &str, is_excluded: bool) -> QueryResult<()>;
+    fn set_blocked(&self, google_user_id: &str, friend_google_user_id: &str, is_blocked: bool, updated_at: NaiveDateTime) -> QueryResult<()>;
+    // Confirmed friends of both users, by name
+    fn mutual_friends(&self, google_user_id: &str, other_google_user_id: &str) -> QueryResult<Vec<PublicProfile>>;
+    // Friends of friends and people who watched any of the user's most recent videos, who have no
+    // userfriends row with the user either way
+    fn friend_suggestions(&self, google_user_id: &str, limit: i64, recent_video_count: i64) -> QueryResult<Vec<FriendSuggestion>>;
+}
+
+pub trait VideoRepository {
+    fn find_video(&self, platform: &str, external_id: &str) -> QueryResult<Option<Video>>;
+    fn find_videos(&self, video_ids: &[i64]) -> QueryResult<Vec<Video>>;
+    fn create_video(&self, new_video: &NewVideo) -> QueryResult<Video>;
+    fn update_video_metadata(&self, video_id: i64, video_metadata_update: &VideoMetadataUpdate) -> QueryResult<Video>;
+    // The view history, the watch history on a first view, the video's counters and the user's
+    // last video all move together
+    fn record_watch(&self, user_id: i64, video_id: i64, watched_at: NaiveDateTime) -> QueryResult<()>;
+    // Every video the user has watched with when they first did, most recent first
+    fn watch_history(&self, user_id: i64, limit: i64, offset: i64) -> QueryResult<Vec<(UserVideo, Video)>>;
+
+    fn find_playlist(&self, platform: &str, external_id: &str) -> QueryResult<Option<Playlist>>;
+    fn create_playlist(&self, new_playlist: &NewPlaylist) -> QueryResult<Playlist>;
+    fn update_playlist_metadata(&self, playlist_id: i64, playlist_metadata_update: &PlaylistMetadataUpdate) -> QueryResult<Playlist>;
+    // The video's stored position in the playlist, None when it is not in it or the position is unknown
+    fn playlist_position(&self, playlist_id: i64, platform: &str, external_id: &str) -> QueryResult<Option<i32>>;
+    // A video already in the playlist moves to the new position
+    fn save_playlist_video(&self, new_playlist_video: &NewPlaylistVideo) -> QueryResult<()>;
+}
+
+// What waits for users while none of their sockets are connected
+pub trait InboxRepository {
+    fn store_notification(&self, new_notification: &NewNotification) -> QueryResult<()>;
+    // Unread notifications oldest first, the ones not delivered before get marked delivered
+    fn take_pending_notifications(&self, google_user_id: &str, limit: i64, delivered_at: NaiveDateTime) -> QueryResult<Vec<Notification>>;
+    // Marks the chat messages sent to the user while they were away as delivered and returns them
+    fn deliver_chat_messages(&self, google_user_id: &str, delivered_at: NaiveDateTime) -> QueryResult<Vec<Message>>;
+    // Marks the given unread notifications of the user read, or all of them without ids, and returns how many
+    fn mark_notifications_read(&self, google_user_id: &str, notification_ids: Option<&[i64]>, read_at: NaiveDateTime) -> QueryResult<usize>;
+}
+
+// Direct messages between friends, delivered when a socket of the recipient gets them and read when
+// the recipient says so
+pub trait ChatRepository {
+    fn create_message(&self, new_message: &NewMessage) -> QueryResult<Message>;
+    fn mark_message_delivered(&self, message_id: i64, delivered_at: NaiveDateTime) -> QueryResult<Message>;
+    // Marks the unread messages from the sender up to the given one read, and delivered if they were not,
+    // and returns them
+    fn mark_messages_read(&self, recipient_google_user_id: &str, sender_google_user_id: &str, up_to_message_id: i64, read_at: NaiveDateTime) -> QueryResult<Vec<Message>>;
+}
+
+// Friends react to the video a friend is watching, the reactions show up in the watcher's history
+pub trait ReactionRepository {
+    fn create_reaction(&self, new_reaction: &NewVideoReaction) -> QueryResult<VideoReaction>;
+    // The reactions to the given videos of the watcher, oldest first, each with the user who reacted
+    fn reactions_to(&self, watcher_google_user_id: &str, video_ids: &[i64]) -> QueryResult<Vec<(VideoReaction, Usermaster)>>;
+}
+
+pub trait ShareRepository {
+    fn create_share(&self, new_video_share: &NewVideoShare) -> QueryResult<VideoShare>;
+}
+
+pub trait InviteRepository {
+    fn create_invite_code(&self, new_invite_code: &NewInviteCode) -> QueryResult<InviteCode>;
+    // Takes one use of the code and records who redeemed it, all or nothing. None when the code is
+    // unknown, used up or expired.
+    fn redeem_invite_code(&self, code: &str, invitee_google_user_id: &str, redeemed_at: NaiveDateTime) -> QueryResult<Option<InviteCode>>;
+}
+
+// What a user's own viewing adds up to. Everything but the taste overlap counts rewatches.
+pub trait StatsRepository {
+    // Views per day, in the user's local time given as their offset from UTC
+    fn watch_days(&self, google_user_id: &str, utc_offset_minutes: i32) -> QueryResult<Vec<WatchCountBucket>>;
+    // Views per week from since on, weeks starting on the user's local Monday
+    fn watch_weeks(&self, google_user_id: &str, utc_offset_minutes: i32, since: NaiveDateTime) -> QueryResult<Vec<WatchCountBucket>>;
+    fn watch_time_totals(&self, google_user_id: &str) -> QueryResult<WatchTimeTotals>;
+    fn top_channels(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>>;
+    fn top_categories(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>>;
+    // Friends whose distinct watched videos overlap the user's the most
+    fn taste_overlaps(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TasteOverlap>>;
+}
+
+// Videos worth watching next, from friends, similar videos and everyone's views
+pub trait DiscoveryRepository {
+    // What friends who have not excluded or blocked the user watched since then, most watched first
+    fn friends_trending(&self, google_user_id: &str, since: NaiveDateTime, limit: i64) -> QueryResult<Vec<TrendingVideo>>;
+    // Neighbours of the user's seed_depth most recent videos they have not watched yet, every friend who
+    // watched one multiplying its score by another friend_viewer_weight
+    fn recommendations(&self, google_user_id: &str, seed_depth: i64, friend_viewer_weight: f64, limit: i64, offset: i64) -> QueryResult<Vec<Recommendation>>;
+    // Replaces the video neighbours with ones computed from every user's history_depth most recent videos,
+    // returns how many pairs were kept
+    fn rebuild_video_similarities(&self, history_depth: i64, neighbours_per_video: i64, rebuilt_at: NaiveDateTime) -> QueryResult<usize>;
+    // since of None ranks over all time
+    fn leaderboard(&self, since: Option<NaiveDateTime>, limit: i64, offset: i64) -> QueryResult<Vec<VideoPopularity>>;
+    // Recomputes the counters on videos from the view history, returns how many videos were off
+    fn rebuild_video_counters(&self) -> QueryResult<usize>;
+}
+
+// What the handlers persist through
+pub struct Storage<'a> {
+    pub users: &'a dyn UserRepository,
+    pub friendships: &'a dyn FriendshipRepository,
+    pub videos: &'a dyn VideoRepository,
+    pub inbox: &'a dyn InboxRepository,
+    pub chat: &'a dyn ChatRepository,
+    pub reactions: &'a dyn ReactionRepository,
+    pub shares: &'a dyn ShareRepository,
+    pub invites: &'a dyn InviteRepository,
+    pub stats: &'a dyn StatsRepository,
+    pub discovery: &'a dyn DiscoveryRepository,
+}
+
+impl<'a> Storage<'a> {
+    pub fn postgres(repository: &'a PgRepository<'a>) -> Storage<'a> {
+        Storage {
+            users: repository,
+            friendships: repository,
+            videos: repository,
+            inbox: repository,
+            chat: repository,
+            reactions: repository,
+            shares: repository,
+            invites: repository,
+            stats: repository,
+            discovery: repository,
+        }
+    }
+
+    pub fn memory(repository: &'a MemoryRepository) -> Storage<'a> {
+        Storage {
+            users: repository,
+            friendships: repository,
+            videos: repository,
+            inbox: repository,
+            chat: repository,
+            reactions: repository,
+            shares: repository,
+            invites: repository,
+            stats: repository,
+            discovery: repository,
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Integer, Text, Timestamp};
+use diesel::PgConnection;
+
+use crate::models::{FriendSuggestion, InviteCode, Message, NewInviteCode, NewInviteRedemption, NewMessage, NewNotification, NewPlaylist, NewPlaylistVideo, NewUser, NewUserFriend, NewUserVideo, NewVideo, NewVideoReaction, NewVideoShare, NewVideoView, Notification, Playlist, PlaylistMetadataUpdate, PublicProfile, Recommendation, TasteOverlap, TopWatchGroup, TrendingVideo, UserFriend, Usermaster, UserVideo, Video, VideoMetadataUpdate, VideoPopularity, VideoReaction, VideoShare, WatchCountBucket, WatchTimeTotals};
+use crate::schema::{invitecodes, inviteredemptions, messages, notifications, playlists, playlistvideos, userfriends, usermaster, uservideos, videoreactions, videos, videoshares, videoviews};
+use super::sql::{
+    ALL_TIME_LEADERBOARD_SQL, FRIENDS_TRENDING_SQL, FRIEND_SUGGESTIONS_SQL, MUTUAL_FRIENDS_SQL, RECOMMENDATIONS_SQL, REBUILD_VIDEO_COUNTERS_SQL,
+    REBUILD_VIDEO_SIMILARITIES_SQL, TASTE_OVERLAP_SQL, TOP_CATEGORIES_SQL, TOP_CHANNELS_SQL, USER_SEARCH_SQL, WATCH_DAYS_SQL,
+    WATCH_TIME_TOTALS_SQL, WATCH_WEEKS_SQL, WINDOWED_LEADERBOARD_SQL,
+};
+use super::{ChatRepository, DiscoveryRepository, FriendshipRepository, InboxRepository, InviteRepository, ReactionRepository, ShareRepository, StatsRepository, UserRepository, VideoRepository};
+
+
+pub struct PgRepository<'a> {
+    pub connection: &'a PgConnection,
+}
+
+impl<'a> PgRepository<'a> {
+    pub fn new(connection: &'a PgConnection) -> PgRepository<'a> {
+        PgRepository { connection }
+    }
+}
+
+// Keeps user typed % and _ literal in LIKE patterns
+fn escape_like_pattern(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl<'a> UserRepository for PgRepository<'a> {
+    fn find_user(&self, google_user_id: &str) -> QueryResult<Option<Usermaster>> {
+        usermaster::table
+            .filter(usermaster::uid.eq(google_user_id))
+            .first::<Usermaster>(self.connection)
+            .optional()
+    }
+
+    fn find_users(&self, google_user_ids: &[&str]) -> QueryResult<Vec<Usermaster>> {
+        usermaster::table
+            .filter(usermaster::uid.eq_any(google_user_ids))
+            .load::<Usermaster>(self.connection)
+    }
+
+    fn create_user(&self, new_user: &NewUser) -> QueryResult<Usermaster> {
+        diesel::insert_into(usermaster::table)
+            .values(new_user)
+            .get_result::<Usermaster>(self.connection)
+    }
+
+    fn update_profile(&self, google_user_id: &str, full_name: &str, image_url: &str, updated_at: NaiveDateTime) -> QueryResult<()> {
+        diesel::update(usermaster::table.filter(usermaster::uid.eq(google_user_id)))
+            .set((
+                usermaster::full_name.eq(full_name),
+                usermaster::image_url.eq(image_url),
+                usermaster::updated_at.eq(updated_at),
+            ))
+            .execute(self.connection)
+            .map(|_| ())
+    }
+
+    fn record_last_seen(&self, google_user_id: &str, seen_at: NaiveDateTime) -> QueryResult<()> {
+        diesel::update(usermaster::table.filter(usermaster::uid.eq(google_user_id)))
+            .set(usermaster::last_seen_at.eq(seen_at))
+            .execute(self.connection)
+            .map(|_| ())
+    }
+
+    fn set_discoverable(&self, google_user_id: &str, is_discoverable: bool, updated_at: NaiveDateTime) -> QueryResult<()> {
+        diesel::update(usermaster::table.filter(usermaster::uid.eq(google_user_id)))
+            .set((
+                usermaster::is_discoverable.eq(is_discoverable),
+                usermaster::updated_at.eq(updated_at),
+            ))
+            .execute(self.connection)
+            .map(|_| ())
+    }
+
+    fn search_users(&self, searcher_google_user_id: &str, query: &str, limit: i64) -> QueryResult<Vec<PublicProfile>> {
+        diesel::sql_query(USER_SEARCH_SQL)
+            .bind::<Text, _>(searcher_google_user_id)
+            .bind::<Text, _>(escape_like_pattern(query))
+            .bind::<Text, _>(query)
+            .bind::<BigInt, _>(limit)
+            .load::<PublicProfile>(self.connection)
+    }
+}
+
+impl<'a> FriendshipRepository for PgRepository<'a> {
+    // Rows in both directions come back in one query, each joined to the other party's user
+    fn friends_of(&self, google_user_id: &str) -> QueryResult<Vec<(UserFriend, Option<UserFriend>, Usermaster)>> {
+        let rows = userfriends::table
+            .inner_join(usermaster::table.on(
+                usermaster::uid.eq(userfriends::friend_google_uid).and(userfriends::user_google_uid.eq(google_user_id))
+                    .or(usermaster::uid.eq(userfriends::user_google_uid).and(userfriends::friend_google_uid.eq(google_user_id)))
+            ))
+            .filter(userfriends::user_google_uid.eq(google_user_id).or(userfriends::friend_google_uid.eq(google_user_id)))
+            .load::<(UserFriend, Usermaster)>(self.connection)?;
+
+        let (my_rows, their_rows): (Vec<_>, Vec<_>) = rows.into_iter()
+            .partition(|(row, _)| row.user_google_uid == google_user_id);
+        let mut rows_back: HashMap<String, UserFriend> = their_rows.into_iter()
+            .map(|(row, _)| (row.user_google_uid.to_owned(), row))
+            .collect();
+
+        Ok(my_rows.into_iter()
+            .map(|(row, friend)| {
+                let row_back = rows_back.remove(&row.friend_google_uid);
+                (row, row_back, friend)
+            })
+            .collect())
+    }
+
+    fn find_friendship(&self, google_user_id: &str, friend_google_user_id: &str) -> QueryResult<Option<UserFriend>> {
+        userfriends::table
+            .filter(
+                userfriends::user_google_uid.eq(google_user_id)
+                    .and(userfriends::friend_google_uid.eq(friend_google_user_id))
+            )
+            .first::<UserFriend>(self.connection)
+            .optional()
+    }
+
+    fn create_friendship(&self, new_friendship: &NewUserFriend) -> QueryResult<()> {
+        diesel::insert_into(userfriends::table)
+            .values(new_friendship)
+            .execute(self.connection)
+            .map(|_| ())
+    }
+
+    fn set_excluded(&self, google_user_id: &str, friend_google_user_id: &str, is_excluded: bool) -> QueryResult<()> {
+        diesel::update(
+            userfriends::table.filter(
+                userfriends::user_google_uid.eq(google_user_id)
+                    .and(userfriends::friend_google_uid.eq(friend_google_user_id))
+            )
+        )
+        .set(userfriends::is_friend_excluded.eq(is_excluded))
+        .execute(self.connection)
+        .map(|_| ())
+    }
+
+    fn set_blocked(&self, google_user_id: &str, friend_google_user_id: &str, is_blocked: bool, updated_at: NaiveDateTime) -> QueryResult<()> {
+        diesel::update(
+            userfriends::table.filter(
+                userfriends::user_google_uid.eq(google_user_id)
+                    .and(userfriends::friend_google_uid.eq(friend_google_user_id))
+            )
+        )
+        .set((
+            userfriends::is_friend_blocked.eq(is_blocked),
+            userfriends::updated_at.eq(updated_at),
+        ))
+        .execute(self.connection)
+        .map(|_| ())
+    }
+
+    fn mutual_friends(&self, google_user_id: &str, other_google_user_id: &str) -> QueryResult<Vec<PublicProfile>> {
+        diesel::sql_query(MUTUAL_FRIENDS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<Text, _>(other_google_user_id)
+            .load::<PublicProfile>(self.connection)
+    }
+
+    fn friend_suggestions(&self, google_user_id: &str, limit: i64, recent_video_count: i64) -> QueryResult<Vec<FriendSuggestion>> {
+        diesel::sql_query(FRIEND_SUGGESTIONS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<BigInt, _>(limit)
+            .bind::<BigInt, _>(recent_video_count)
+            .load::<FriendSuggestion>(self.connection)
+    }
+}
+
+impl<'a> VideoRepository for PgRepository<'a> {
+    fn find_video(&self, platform: &str, external_id: &str) -> QueryResult<Option<Video>> {
+        videos::table
+            .filter(videos::platform.eq(platform).and(videos::external_id.eq(external_id)))
+            .first::<Video>(self.connection)
+            .optional()
+    }
+
+    fn find_videos(&self, video_ids: &[i64]) -> QueryResult<Vec<Video>> {
+        videos::table
+            .filter(videos::id.eq_any(video_ids))
+            .load::<Video>(self.connection)
+    }
+
+    fn create_video(&self, new_video: &NewVideo) -> QueryResult<Video> {
+        diesel::insert_into(videos::table)
+            .values(new_video)
+            .get_result::<Video>(self.connection)
+    }
+
+    fn update_video_metadata(&self, video_id: i64, video_metadata_update: &VideoMetadataUpdate) -> QueryResult<Video> {
+        diesel::update(videos::table.find(video_id))
+            .set(video_metadata_update)
+            .get_result::<Video>(self.connection)
+    }
+
+    // The counters on videos stay in step with videoviews and uservideos, rebuild-video-counters recomputes them
+    fn record_watch(&self, user_id: i64, video_id: i64, watched_at: NaiveDateTime) -> QueryResult<()> {
+        self.connection.transaction(|| {
+            diesel::insert_into(videoviews::table)
+                .values(&NewVideoView { video_id, user_id, created_at: watched_at })
+                .execute(self.connection)?;
+
+            let existing_user_video = uservideos::table
+                .filter(uservideos::user_id.eq(user_id).and(uservideos::video_id.eq(video_id)))
+                .load::<UserVideo>(self.connection)?;
+
+            let is_new_viewer = existing_user_video.is_empty();
+            if is_new_viewer {
+                diesel::insert_into(uservideos::table)
+                    .values(&NewUserVideo { user_id, video_id, created_at: watched_at })
+                    .execute(self.connection)?;
+            }
+
+            diesel::update(videos::table.find(video_id))
+                .set((
+                    videos::view_count.eq(videos::view_count + 1),
+                    videos::unique_viewer_count.eq(videos::unique_viewer_count + if is_new_viewer { 1 } else { 0 }),
+                ))
+                .execute(self.connection)?;
+
+            diesel::update(usermaster::table.find(user_id))
+                .set((
+                    usermaster::last_seen_at.eq(watched_at),
+                    usermaster::last_video_id.eq(video_id),
+                ))
+                .execute(self.connection)?;
+
+            Ok(())
+        })
+    }
+
+    fn watch_history(&self, user_id: i64, limit: i64, offset: i64) -> QueryResult<Vec<(UserVideo, Video)>> {
+        uservideos::table
+            .inner_join(videos::table)
+            .filter(uservideos::user_id.eq(user_id))
+            .order(uservideos::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<(UserVideo, Video)>(self.connection)
+    }
+
+    fn find_playlist(&self, platform: &str, external_id: &str) -> QueryResult<Option<Playlist>> {
+        playlists::table
+            .filter(playlists::platform.eq(platform).and(playlists::external_id.eq(external_id)))
+            .first::<Playlist>(self.connection)
+            .optional()
+    }
+
+    fn create_playlist(&self, new_playlist: &NewPlaylist) -> QueryResult<Playlist> {
+        diesel::insert_into(playlists::table)
+            .values(new_playlist)
+            .get_result::<Playlist>(self.connection)
+    }
+
+    fn update_playlist_metadata(&self, playlist_id: i64, playlist_metadata_update: &PlaylistMetadataUpdate) -> QueryResult<Playlist> {
+        diesel::update(playlists::table.find(playlist_id))
+            .set(playlist_metadata_update)
+            .get_result::<Playlist>(self.connection)
+    }
+
+    fn playlist_position(&self, playlist_id: i64, platform: &str, external_id: &str) -> QueryResult<Option<i32>> {
+        playlistvideos::table
+            .inner_join(videos::table)
+            .filter(
+                playlistvideos::playlist_id.eq(playlist_id)
+                    .and(videos::platform.eq(platform))
+                    .and(videos::external_id.eq(external_id))
+            )
+            .select(playlistvideos::position)
+            .first::<Option<i32>>(self.connection)
+            .optional()
+            .map(Option::flatten)
+    }
+
+    fn save_playlist_video(&self, new_playlist_video: &NewPlaylistVideo) -> QueryResult<()> {
+        diesel::insert_into(playlistvideos::table)
+            .values(new_playlist_video)
+            .on_conflict((playlistvideos::playlist_id, playlistvideos::video_id))
+            .do_update()
+            .set((
+                playlistvideos::position.eq(new_playlist_video.position),
+                playlistvideos::updated_at.eq(new_playlist_video.created_at),
+            ))
+            .execute(self.connection)
+            .map(|_| ())
+    }
+}
+
+impl<'a> InboxRepository for PgRepository<'a> {
+    fn store_notification(&self, new_notification: &NewNotification) -> QueryResult<()> {
+        diesel::insert_into(notifications::table)
+            .values(new_notification)
+            .execute(self.connection)
+            .map(|_| ())
+    }
+
+    fn take_pending_notifications(&self, google_user_id: &str, limit: i64, delivered_at: NaiveDateTime) -> QueryResult<Vec<Notification>> {
+        let pending_notifications = notifications::table
+            .filter(notifications::recipient_google_uid.eq(google_user_id).and(notifications::read_at.is_null()))
+            .order(notifications::id.asc())
+            .limit(limit)
+            .load::<Notification>(self.connection)?;
+
+        let undelivered_ids: Vec<i64> = pending_notifications.iter()
+            .filter(|notification| notification.delivered_at.is_none())
+            .map(|notification| notification.id)
+            .collect();
+
+        if !undelivered_ids.is_empty() {
+            diesel::update(notifications::table.filter(notifications::id.eq_any(&undelivered_ids)))
+                .set(notifications::delivered_at.eq(delivered_at))
+                .execute(self.connection)?;
+        }
+
+        Ok(pending_notifications)
+    }
+
+    fn deliver_chat_messages(&self, google_user_id: &str, delivered_at: NaiveDateTime) -> QueryResult<Vec<Message>> {
+        diesel::update(
+            messages::table.filter(messages::recipient_google_uid.eq(google_user_id).and(messages::delivered_at.is_null()))
+        )
+        .set((messages::delivered_at.eq(delivered_at), messages::updated_at.eq(delivered_at)))
+        .get_results::<Message>(self.connection)
+    }
+
+    fn mark_notifications_read(&self, google_user_id: &str, notification_ids: Option<&[i64]>, read_at: NaiveDateTime) -> QueryResult<usize> {
+        let unread_notifications = notifications::table.filter(
+            notifications::recipient_google_uid.eq(google_user_id).and(notifications::read_at.is_null())
+        );
+        let mark_read = (notifications::read_at.eq(read_at), notifications::updated_at.eq(read_at));
+
+        match notification_ids {
+            Some(notification_ids) => diesel::update(unread_notifications.filter(notifications::id.eq_any(notification_ids)))
+                .set(mark_read)
+                .execute(self.connection),
+            None => diesel::update(unread_notifications)
+                .set(mark_read)
+                .execute(self.connection)
+        }
+    }
+}
+
+impl<'a> ChatRepository for PgRepository<'a> {
+    fn create_message(&self, new_message: &NewMessage) -> QueryResult<Message> {
+        diesel::insert_into(messages::table)
+            .values(new_message)
+            .get_result::<Message>(self.connection)
+    }
+
+    fn mark_message_delivered(&self, message_id: i64, delivered_at: NaiveDateTime) -> QueryResult<Message> {
+        diesel::update(messages::table.find(message_id))
+            .set(messages::delivered_at.eq(delivered_at))
+            .get_result::<Message>(self.connection)
+    }
+
+    fn mark_messages_read(&self, recipient_google_user_id: &str, sender_google_user_id: &str, up_to_message_id: i64, read_at: NaiveDateTime) -> QueryResult<Vec<Message>> {
+        let unread_messages = messages::table.filter(
+            messages::recipient_google_uid.eq(recipient_google_user_id)
+                .and(messages::sender_google_uid.eq(sender_google_user_id))
+                .and(messages::id.le(up_to_message_id))
+                .and(messages::read_at.is_null())
+        );
+
+        self.connection.transaction(|| {
+            // A message can't have been read without being delivered
+            diesel::update(unread_messages.filter(messages::delivered_at.is_null()))
+                .set(messages::delivered_at.eq(read_at))
+                .execute(self.connection)?;
+
+            diesel::update(unread_messages)
+                .set((messages::read_at.eq(read_at), messages::updated_at.eq(read_at)))
+                .get_results::<Message>(self.connection)
+        })
+    }
+}
+
+impl<'a> ReactionRepository for PgRepository<'a> {
+    fn create_reaction(&self, new_reaction: &NewVideoReaction) -> QueryResult<VideoReaction> {
+        diesel::insert_into(videoreactions::table)
+            .values(new_reaction)
+            .get_result::<VideoReaction>(self.connection)
+    }
+
+    fn reactions_to(&self, watcher_google_user_id: &str, video_ids: &[i64]) -> QueryResult<Vec<(VideoReaction, Usermaster)>> {
+        videoreactions::table
+            .inner_join(usermaster::table.on(usermaster::uid.eq(videoreactions::reactor_google_uid)))
+            .filter(
+                videoreactions::watcher_google_uid.eq(watcher_google_user_id)
+                    .and(videoreactions::video_id.eq_any(video_ids))
+            )
+            .order(videoreactions::created_at.asc())
+            .load::<(VideoReaction, Usermaster)>(self.connection)
+    }
+}
+
+impl<'a> ShareRepository for PgRepository<'a> {
+    fn create_share(&self, new_video_share: &NewVideoShare) -> QueryResult<VideoShare> {
+        diesel::insert_into(videoshares::table)
+            .values(new_video_share)
+            .get_result::<VideoShare>(self.connection)
+    }
+}
+
+impl<'a> InviteRepository for PgRepository<'a> {
+    fn create_invite_code(&self, new_invite_code: &NewInviteCode) -> QueryResult<InviteCode> {
+        diesel::insert_into(invitecodes::table)
+            .values(new_invite_code)
+            .get_result::<InviteCode>(self.connection)
+    }
+
+    // The use_count check and increment happen in one statement so concurrent sign ups cannot overdraw a code,
+    // and the use is given back when the redemption cannot be recorded
+    fn redeem_invite_code(&self, code: &str, invitee_google_user_id: &str, redeemed_at: NaiveDateTime) -> QueryResult<Option<InviteCode>> {
+        self.connection.transaction(|| {
+            let claimed_code = diesel::update(
+                invitecodes::table.filter(
+                    invitecodes::code.eq(code)
+                        .and(invitecodes::max_uses.is_null().or(invitecodes::use_count.nullable().lt(invitecodes::max_uses)))
+                        .and(invitecodes::expires_at.is_null().or(invitecodes::expires_at.gt(redeemed_at)))
+                )
+            )
+            .set((
+                invitecodes::use_count.eq(invitecodes::use_count + 1),
+                invitecodes::updated_at.eq(redeemed_at),
+            ))
+            .get_result::<InviteCode>(self.connection)
+            .optional()?;
+
+            if let Some(ref claimed_code) = claimed_code {
+                let new_invite_redemption = NewInviteRedemption {
+                    invite_code_id: claimed_code.id,
+                    invitee_google_uid: invitee_google_user_id,
+                    created_at: redeemed_at,
+                };
+
+                diesel::insert_into(inviteredemptions::table)
+                    .values(&new_invite_redemption)
+                    .execute(self.connection)?;
+            }
+            Ok(claimed_code)
+        })
+    }
+}
+
+impl<'a> StatsRepository for PgRepository<'a> {
+    fn watch_days(&self, google_user_id: &str, utc_offset_minutes: i32) -> QueryResult<Vec<WatchCountBucket>> {
+        diesel::sql_query(WATCH_DAYS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<Integer, _>(utc_offset_minutes)
+            .load::<WatchCountBucket>(self.connection)
+    }
+
+    fn watch_weeks(&self, google_user_id: &str, utc_offset_minutes: i32, since: NaiveDateTime) -> QueryResult<Vec<WatchCountBucket>> {
+        diesel::sql_query(WATCH_WEEKS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<Integer, _>(utc_offset_minutes)
+            .bind::<Timestamp, _>(since)
+            .load::<WatchCountBucket>(self.connection)
+    }
+
+    fn watch_time_totals(&self, google_user_id: &str) -> QueryResult<WatchTimeTotals> {
+        diesel::sql_query(WATCH_TIME_TOTALS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .get_result::<WatchTimeTotals>(self.connection)
+    }
+
+    fn top_channels(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>> {
+        diesel::sql_query(TOP_CHANNELS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<BigInt, _>(limit)
+            .load::<TopWatchGroup>(self.connection)
+    }
+
+    fn top_categories(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TopWatchGroup>> {
+        diesel::sql_query(TOP_CATEGORIES_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<BigInt, _>(limit)
+            .load::<TopWatchGroup>(self.connection)
+    }
+
+    fn taste_overlaps(&self, google_user_id: &str, limit: i64) -> QueryResult<Vec<TasteOverlap>> {
+        diesel::sql_query(TASTE_OVERLAP_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<BigInt, _>(limit)
+            .load::<TasteOverlap>(self.connection)
+    }
+}
+
+impl<'a> DiscoveryRepository for PgRepository<'a> {
+    fn friends_trending(&self, google_user_id: &str, since: NaiveDateTime, limit: i64) -> QueryResult<Vec<TrendingVideo>> {
+        diesel::sql_query(FRIENDS_TRENDING_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<Timestamp, _>(since)
+            .bind::<BigInt, _>(limit)
+            .load::<TrendingVideo>(self.connection)
+    }
+
+    fn recommendations(&self, google_user_id: &str, seed_depth: i64, friend_viewer_weight: f64, limit: i64, offset: i64) -> QueryResult<Vec<Recommendation>> {
+        diesel::sql_query(RECOMMENDATIONS_SQL)
+            .bind::<Text, _>(google_user_id)
+            .bind::<BigInt, _>(seed_depth)
+            .bind::<Double, _>(friend_viewer_weight)
+            .bind::<BigInt, _>(limit)
+            .bind::<BigInt, _>(offset)
+            .load::<Recommendation>(self.connection)
+    }
+
+    fn rebuild_video_similarities(&self, history_depth: i64, neighbours_per_video: i64, rebuilt_at: NaiveDateTime) -> QueryResult<usize> {
+        self.connection.transaction(|| {
+            diesel::sql_query("DELETE FROM videosimilarities").execute(self.connection)?;
+
+            diesel::sql_query(REBUILD_VIDEO_SIMILARITIES_SQL)
+                .bind::<BigInt, _>(history_depth)
+                .bind::<BigInt, _>(neighbours_per_video)
+                .bind::<Timestamp, _>(rebuilt_at)
+                .execute(self.connection)
+        })
+    }
+
+    fn leaderboard(&self, since: Option<NaiveDateTime>, limit: i64, offset: i64) -> QueryResult<Vec<VideoPopularity>> {
+        match since {
+            Some(since) => diesel::sql_query(WINDOWED_LEADERBOARD_SQL)
+                .bind::<Timestamp, _>(since)
+                .bind::<BigInt, _>(limit)
+                .bind::<BigInt, _>(offset)
+                .load::<VideoPopularity>(self.connection),
+            None => diesel::sql_query(ALL_TIME_LEADERBOARD_SQL)
+                .bind::<BigInt, _>(limit)
+                .bind::<BigInt, _>(offset)
+                .load::<VideoPopularity>(self.connection),
+        }
+    }
+
+    fn rebuild_video_counters(&self) -> QueryResult<usize> {
+        diesel::sql_query(REBUILD_VIDEO_COUNTERS_SQL).execute(self.connection)
+    }
+}
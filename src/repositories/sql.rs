@@ -0,0 +1,343 @@
+// The queries diesel's query builder can't express
+
+// Candidates are friends of friends and people who watched the same videos. Anyone with a
+// userfriends row towards or from the user, be it a friendship, a pending request or a block, is left out.
+// That leaves only strangers, so users who turned discoverability off are never suggested.
+pub(super) const FRIEND_SUGGESTIONS_SQL: &str = "
+    WITH my_friends AS (
+        SELECT mine.friend_google_uid AS uid
+        FROM userfriends mine
+        JOIN userfriends reverse ON reverse.user_google_uid = mine.friend_google_uid AND reverse.friend_google_uid = $1
+        WHERE mine.user_google_uid = $1 AND NOT mine.is_friend_blocked AND NOT reverse.is_friend_blocked
+    ),
+    mutual_friends AS (
+        SELECT theirs.friend_google_uid AS uid, COUNT(*) AS mutual_friend_count
+        FROM my_friends
+        JOIN userfriends theirs ON theirs.user_google_uid = my_friends.uid AND NOT theirs.is_friend_blocked
+        WHERE theirs.friend_google_uid <> $1
+        GROUP BY theirs.friend_google_uid
+    ),
+    my_videos AS (
+        SELECT DISTINCT recent.video_id
+        FROM (
+            SELECT uservideos.video_id
+            FROM uservideos
+            JOIN usermaster ON usermaster.id = uservideos.user_id
+            WHERE usermaster.uid = $1
+            ORDER BY uservideos.created_at DESC
+            LIMIT $3
+        ) recent
+    ),
+    shared_videos AS (
+        SELECT usermaster.uid, COUNT(DISTINCT uservideos.video_id) AS shared_video_count
+        FROM my_videos
+        JOIN uservideos ON uservideos.video_id = my_videos.video_id
+        JOIN usermaster ON usermaster.id = uservideos.user_id
+        WHERE usermaster.uid <> $1
+        GROUP BY usermaster.uid
+    )
+    SELECT usermaster.uid, usermaster.full_name, usermaster.image_url,
+           COALESCE(mutual_friends.mutual_friend_count, 0) AS mutual_friend_count,
+           COALESCE(shared_videos.shared_video_count, 0) AS shared_video_count
+    FROM mutual_friends
+    FULL OUTER JOIN shared_videos ON shared_videos.uid = mutual_friends.uid
+    JOIN usermaster ON usermaster.uid = COALESCE(mutual_friends.uid, shared_videos.uid)
+    WHERE usermaster.is_discoverable
+      AND NOT EXISTS (
+          SELECT 1 FROM userfriends existing
+          WHERE (existing.user_google_uid = $1 AND existing.friend_google_uid = usermaster.uid)
+             OR (existing.user_google_uid = usermaster.uid AND existing.friend_google_uid = $1)
+      )
+    ORDER BY mutual_friend_count DESC, shared_video_count DESC, usermaster.full_name ASC
+    LIMIT $2
+";
+
+// search_name() lowercases and strips accents, see the user_search migration. Users who turned
+// discoverability off are only found by people they already have a userfriends row with.
+pub(super) const USER_SEARCH_SQL: &str = "
+    SELECT usermaster.uid, usermaster.full_name, usermaster.image_url
+    FROM usermaster
+    WHERE usermaster.uid <> $1
+      AND (
+          usermaster.is_discoverable
+          OR EXISTS (
+              SELECT 1 FROM userfriends known
+              WHERE (known.user_google_uid = $1 AND known.friend_google_uid = usermaster.uid)
+                 OR (known.user_google_uid = usermaster.uid AND known.friend_google_uid = $1)
+          )
+      )
+      AND NOT EXISTS (
+          SELECT 1 FROM userfriends blocked
+          WHERE blocked.is_friend_blocked
+            AND ((blocked.user_google_uid = $1 AND blocked.friend_google_uid = usermaster.uid)
+              OR (blocked.user_google_uid = usermaster.uid AND blocked.friend_google_uid = $1))
+      )
+      AND (
+          search_name(usermaster.full_name) LIKE search_name($2) || '%'
+          OR search_name(usermaster.full_name) LIKE '% ' || search_name($2) || '%'
+          OR search_name($3) <% search_name(usermaster.full_name)
+      )
+    ORDER BY search_name(usermaster.full_name) LIKE search_name($2) || '%' DESC,
+             word_similarity(search_name($3), search_name(usermaster.full_name)) DESC,
+             usermaster.full_name ASC
+    LIMIT $4
+";
+
+// Friends on both sides, leaving out anyone either of the two has blocked
+pub(super) const MUTUAL_FRIENDS_SQL: &str = "
+    SELECT usermaster.uid, usermaster.full_name, usermaster.image_url
+    FROM usermaster
+    WHERE usermaster.uid IN (
+        SELECT mine.friend_google_uid
+        FROM userfriends mine
+        JOIN userfriends mine_reverse
+          ON mine_reverse.user_google_uid = mine.friend_google_uid AND mine_reverse.friend_google_uid = mine.user_google_uid
+        WHERE mine.user_google_uid = $1 AND NOT mine.is_friend_blocked AND NOT mine_reverse.is_friend_blocked
+        INTERSECT
+        SELECT theirs.friend_google_uid
+        FROM userfriends theirs
+        JOIN userfriends theirs_reverse
+          ON theirs_reverse.user_google_uid = theirs.friend_google_uid AND theirs_reverse.friend_google_uid = theirs.user_google_uid
+        WHERE theirs.user_google_uid = $2 AND NOT theirs.is_friend_blocked AND NOT theirs_reverse.is_friend_blocked
+    )
+    ORDER BY usermaster.full_name ASC
+";
+
+// Friends count when the friendship exists on both sides and neither side excluded or blocked the other
+pub(super) const FRIENDS_TRENDING_SQL: &str = "
+    WITH friends AS (
+        SELECT mine.friend_google_uid AS uid
+        FROM userfriends mine
+        JOIN userfriends theirs
+          ON theirs.user_google_uid = mine.friend_google_uid AND theirs.friend_google_uid = mine.user_google_uid
+        WHERE mine.user_google_uid = $1
+          AND NOT mine.is_friend_excluded AND NOT mine.is_friend_blocked
+          AND NOT theirs.is_friend_excluded AND NOT theirs.is_friend_blocked
+    ),
+    friend_views AS (
+        SELECT uservideos.video_id, usermaster.uid, MAX(uservideos.created_at) AS watched_at
+        FROM friends
+        JOIN usermaster ON usermaster.uid = friends.uid
+        JOIN uservideos ON uservideos.user_id = usermaster.id
+        WHERE uservideos.created_at >= $2
+        GROUP BY uservideos.video_id, usermaster.uid
+    )
+    SELECT video_id,
+           COUNT(*) AS viewer_count,
+           MAX(watched_at) AS last_watched_at,
+           array_agg(uid ORDER BY watched_at DESC) AS viewer_google_uids
+    FROM friend_views
+    GROUP BY video_id
+    ORDER BY viewer_count DESC, last_watched_at DESC
+    LIMIT $3
+";
+
+// Cosine similarity over the sets of users who watched each video
+pub(super) const REBUILD_VIDEO_SIMILARITIES_SQL: &str = "
+    INSERT INTO videosimilarities (video_id, similar_video_id, co_watch_count, score, created_at)
+    WITH recent_history AS (
+        SELECT user_id, video_id
+        FROM (
+            SELECT user_id, video_id,
+                   row_number() OVER (PARTITION BY user_id ORDER BY created_at DESC) AS recency
+            FROM uservideos
+        ) ranked_history
+        WHERE recency <= $1
+    ),
+    video_viewers AS (
+        SELECT video_id, COUNT(DISTINCT user_id) AS viewer_count
+        FROM recent_history
+        GROUP BY video_id
+    ),
+    co_watches AS (
+        SELECT watched.video_id, also_watched.video_id AS similar_video_id,
+               COUNT(DISTINCT watched.user_id) AS co_watch_count
+        FROM recent_history watched
+        JOIN recent_history also_watched
+          ON also_watched.user_id = watched.user_id AND also_watched.video_id <> watched.video_id
+        GROUP BY watched.video_id, also_watched.video_id
+    ),
+    scored AS (
+        SELECT co_watches.video_id, co_watches.similar_video_id, co_watches.co_watch_count,
+               co_watches.co_watch_count / sqrt(viewers.viewer_count::float8 * similar_viewers.viewer_count) AS score
+        FROM co_watches
+        JOIN video_viewers viewers ON viewers.video_id = co_watches.video_id
+        JOIN video_viewers similar_viewers ON similar_viewers.video_id = co_watches.similar_video_id
+    )
+    SELECT video_id, similar_video_id, co_watch_count, score, $3
+    FROM (
+        SELECT scored.*,
+               row_number() OVER (PARTITION BY video_id ORDER BY score DESC, co_watch_count DESC, similar_video_id) AS neighbour_rank
+        FROM scored
+    ) ranked_neighbours
+    WHERE neighbour_rank <= $2
+";
+
+// Candidates are neighbours of the user's recent videos that they have not watched yet,
+// boosted by how many of their friends watched them
+pub(super) const RECOMMENDATIONS_SQL: &str = "
+    WITH my_history AS (
+        SELECT uservideos.video_id, uservideos.created_at
+        FROM uservideos
+        JOIN usermaster ON usermaster.id = uservideos.user_id
+        WHERE usermaster.uid = $1
+    ),
+    seed_videos AS (
+        SELECT video_id FROM my_history ORDER BY created_at DESC LIMIT $2
+    ),
+    friends AS (
+        SELECT mine.friend_google_uid AS uid
+        FROM userfriends mine
+        JOIN userfriends theirs
+          ON theirs.user_google_uid = mine.friend_google_uid AND theirs.friend_google_uid = mine.user_google_uid
+        WHERE mine.user_google_uid = $1
+          AND NOT mine.is_friend_excluded AND NOT mine.is_friend_blocked
+          AND NOT theirs.is_friend_excluded AND NOT theirs.is_friend_blocked
+    ),
+    candidates AS (
+        SELECT videosimilarities.similar_video_id AS video_id, SUM(videosimilarities.score) AS similarity_score
+        FROM seed_videos
+        JOIN videosimilarities ON videosimilarities.video_id = seed_videos.video_id
+        WHERE videosimilarities.similar_video_id NOT IN (SELECT video_id FROM my_history)
+        GROUP BY videosimilarities.similar_video_id
+    ),
+    friend_viewers AS (
+        SELECT uservideos.video_id, COUNT(DISTINCT uservideos.user_id) AS friend_viewer_count
+        FROM candidates
+        JOIN uservideos ON uservideos.video_id = candidates.video_id
+        JOIN usermaster ON usermaster.id = uservideos.user_id
+        JOIN friends ON friends.uid = usermaster.uid
+        GROUP BY uservideos.video_id
+    )
+    SELECT candidates.video_id,
+           candidates.similarity_score * (1 + $3 * COALESCE(friend_viewers.friend_viewer_count, 0)) AS score,
+           COALESCE(friend_viewers.friend_viewer_count, 0) AS friend_viewer_count
+    FROM candidates
+    LEFT JOIN friend_viewers ON friend_viewers.video_id = candidates.video_id
+    ORDER BY score DESC, candidates.video_id DESC
+    LIMIT $4 OFFSET $5
+";
+
+// Everything but the taste overlap counts rewatches, so it reads the videoviews log rather than uservideos.
+// Day buckets are in the user's local time, $2 being their offset from UTC in minutes
+pub(super) const WATCH_DAYS_SQL: &str = "
+    SELECT (videoviews.created_at + make_interval(mins => $2))::date AS bucket, COUNT(*) AS video_count
+    FROM videoviews
+    JOIN usermaster ON usermaster.id = videoviews.user_id
+    WHERE usermaster.uid = $1
+    GROUP BY bucket
+    ORDER BY bucket ASC
+";
+
+pub(super) const WATCH_WEEKS_SQL: &str = "
+    SELECT date_trunc('week', videoviews.created_at + make_interval(mins => $2))::date AS bucket, COUNT(*) AS video_count
+    FROM videoviews
+    JOIN usermaster ON usermaster.id = videoviews.user_id
+    WHERE usermaster.uid = $1 AND videoviews.created_at >= $3
+    GROUP BY bucket
+    ORDER BY bucket ASC
+";
+
+// Live broadcasts and videos whose metadata never resolved have no duration
+pub(super) const WATCH_TIME_TOTALS_SQL: &str = "
+    SELECT COUNT(*) AS video_count,
+           COALESCE(SUM(videos.duration_seconds), 0)::int8 AS watch_seconds,
+           COUNT(*) FILTER (WHERE videos.duration_seconds IS NULL) AS unknown_duration_count
+    FROM videoviews
+    JOIN usermaster ON usermaster.id = videoviews.user_id
+    JOIN videos ON videos.id = videoviews.video_id
+    WHERE usermaster.uid = $1
+";
+
+pub(super) const TOP_CHANNELS_SQL: &str = "
+    SELECT videos.channel_id AS group_id, MAX(videos.channel_title) AS group_title, COUNT(*) AS video_count
+    FROM videoviews
+    JOIN usermaster ON usermaster.id = videoviews.user_id
+    JOIN videos ON videos.id = videoviews.video_id
+    WHERE usermaster.uid = $1 AND videos.channel_id IS NOT NULL
+    GROUP BY videos.channel_id
+    ORDER BY video_count DESC, group_title ASC
+    LIMIT $2
+";
+
+pub(super) const TOP_CATEGORIES_SQL: &str = "
+    SELECT videos.category_id AS group_id, NULL::text AS group_title, COUNT(*) AS video_count
+    FROM videoviews
+    JOIN usermaster ON usermaster.id = videoviews.user_id
+    JOIN videos ON videos.id = videoviews.video_id
+    WHERE usermaster.uid = $1 AND videos.category_id IS NOT NULL
+    GROUP BY videos.category_id
+    ORDER BY video_count DESC, group_id ASC
+    LIMIT $2
+";
+
+// Jaccard similarity of the two watch histories, over friends who have not excluded or blocked each other
+pub(super) const TASTE_OVERLAP_SQL: &str = "
+    WITH my_videos AS (
+        SELECT DISTINCT uservideos.video_id
+        FROM uservideos
+        JOIN usermaster ON usermaster.id = uservideos.user_id
+        WHERE usermaster.uid = $1
+    ),
+    friends AS (
+        SELECT mine.friend_google_uid AS uid
+        FROM userfriends mine
+        JOIN userfriends theirs
+          ON theirs.user_google_uid = mine.friend_google_uid AND theirs.friend_google_uid = mine.user_google_uid
+        WHERE mine.user_google_uid = $1
+          AND NOT mine.is_friend_excluded AND NOT mine.is_friend_blocked
+          AND NOT theirs.is_friend_excluded AND NOT theirs.is_friend_blocked
+    ),
+    overlap AS (
+        SELECT usermaster.uid,
+               COUNT(DISTINCT uservideos.video_id) FILTER (WHERE my_videos.video_id IS NOT NULL) AS shared_video_count,
+               COUNT(DISTINCT uservideos.video_id) AS their_video_count
+        FROM friends
+        JOIN usermaster ON usermaster.uid = friends.uid
+        JOIN uservideos ON uservideos.user_id = usermaster.id
+        LEFT JOIN my_videos ON my_videos.video_id = uservideos.video_id
+        GROUP BY usermaster.uid
+    )
+    SELECT usermaster.uid, usermaster.full_name, usermaster.image_url, overlap.shared_video_count,
+           overlap.shared_video_count::float8
+               / (overlap.their_video_count + (SELECT COUNT(*) FROM my_videos) - overlap.shared_video_count) AS similarity
+    FROM overlap
+    JOIN usermaster ON usermaster.uid = overlap.uid
+    WHERE overlap.shared_video_count > 0
+    ORDER BY similarity DESC, overlap.shared_video_count DESC
+    LIMIT $2
+";
+
+// All time rankings come straight off the counters kept on videos
+pub(super) const ALL_TIME_LEADERBOARD_SQL: &str = "
+    SELECT id AS video_id, view_count, unique_viewer_count
+    FROM videos
+    WHERE view_count > 0
+    ORDER BY view_count DESC, unique_viewer_count DESC, id DESC
+    LIMIT $1 OFFSET $2
+";
+
+// Windowed rankings only scan the views inside the window, through videoviews_created_at_idx
+pub(super) const WINDOWED_LEADERBOARD_SQL: &str = "
+    SELECT video_id, COUNT(*) AS view_count, COUNT(DISTINCT user_id) AS unique_viewer_count
+    FROM videoviews
+    WHERE created_at >= $1
+    GROUP BY video_id
+    ORDER BY view_count DESC, unique_viewer_count DESC, video_id DESC
+    LIMIT $2 OFFSET $3
+";
+
+pub(super) const REBUILD_VIDEO_COUNTERS_SQL: &str = "
+    UPDATE videos SET
+        view_count = COALESCE(counts.view_count, 0),
+        unique_viewer_count = COALESCE(counts.unique_viewer_count, 0)
+    FROM videos counted_videos
+    LEFT JOIN (
+        SELECT video_id, COUNT(*) AS view_count, COUNT(DISTINCT user_id) AS unique_viewer_count
+        FROM videoviews
+        GROUP BY video_id
+    ) counts ON counts.video_id = counted_videos.id
+    WHERE videos.id = counted_videos.id
+      AND (videos.view_count <> COALESCE(counts.view_count, 0)
+        OR videos.unique_viewer_count <> COALESCE(counts.unique_viewer_count, 0))
+";
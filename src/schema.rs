@@ -1,3 +1,78 @@
+table! {
+    invitecodes (id) {
+        id -> Int8,
+        code -> Text,
+        inviter_google_uid -> Text,
+        max_uses -> Nullable<Int4>,
+        use_count -> Int4,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    inviteredemptions (id) {
+        id -> Int8,
+        invite_code_id -> Int8,
+        invitee_google_uid -> Text,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    messages (id) {
+        id -> Int8,
+        sender_google_uid -> Text,
+        recipient_google_uid -> Text,
+        body -> Text,
+        delivered_at -> Nullable<Timestamp>,
+        read_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Int8,
+        recipient_google_uid -> Text,
+        action -> Text,
+        payload -> Text,
+        delivered_at -> Nullable<Timestamp>,
+        read_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    playlists (id) {
+        id -> Int8,
+        platform -> Text,
+        external_id -> Text,
+        title -> Text,
+        channel_id -> Nullable<Text>,
+        channel_title -> Nullable<Text>,
+        item_count -> Nullable<Int8>,
+        thumbnail_url -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    playlistvideos (id) {
+        id -> Int8,
+        playlist_id -> Int8,
+        video_id -> Int8,
+        position -> Nullable<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     userfriends (id) {
         id -> Int8,
@@ -6,6 +81,7 @@ table! {
         is_friend_excluded -> Bool,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        is_friend_blocked -> Bool,
     }
 }
 
@@ -18,6 +94,9 @@ table! {
         image_url -> Text,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        is_discoverable -> Bool,
+        last_seen_at -> Nullable<Timestamp>,
+        last_video_id -> Nullable<Int8>,
     }
 }
 
@@ -31,23 +110,98 @@ table! {
     }
 }
 
+table! {
+    videosimilarities (video_id, similar_video_id) {
+        video_id -> Int8,
+        similar_video_id -> Int8,
+        co_watch_count -> Int8,
+        score -> Float8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    videoshares (id) {
+        id -> Int8,
+        video_id -> Int8,
+        sender_google_uid -> Text,
+        recipient_google_uid -> Text,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     videos (id) {
         id -> Int8,
         video_url -> Text,
-        youtube_video_id -> Text,
+        external_id -> Text,
         video_title -> Text,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        channel_id -> Nullable<Text>,
+        channel_title -> Nullable<Text>,
+        published_at -> Nullable<Timestamp>,
+        category_id -> Nullable<Text>,
+        thumbnail_default_url -> Nullable<Text>,
+        thumbnail_medium_url -> Nullable<Text>,
+        thumbnail_high_url -> Nullable<Text>,
+        thumbnail_maxres_url -> Nullable<Text>,
+        duration_seconds -> Nullable<Int8>,
+        is_live_broadcast -> Bool,
+        platform -> Text,
+        view_count -> Int8,
+        unique_viewer_count -> Int8,
+    }
+}
+
+table! {
+    videoviews (id) {
+        id -> Int8,
+        video_id -> Int8,
+        user_id -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    videoreactions (id) {
+        id -> Int8,
+        video_id -> Int8,
+        watcher_google_uid -> Text,
+        reactor_google_uid -> Text,
+        emoji -> Nullable<Text>,
+        comment -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(inviteredemptions -> invitecodes (invite_code_id));
+joinable!(playlistvideos -> playlists (playlist_id));
+joinable!(playlistvideos -> videos (video_id));
+joinable!(usermaster -> videos (last_video_id));
 joinable!(uservideos -> usermaster (user_id));
 joinable!(uservideos -> videos (video_id));
+joinable!(videoreactions -> videos (video_id));
+joinable!(videoshares -> videos (video_id));
+joinable!(videoviews -> usermaster (user_id));
+joinable!(videoviews -> videos (video_id));
 
 allow_tables_to_appear_in_same_query!(
+    invitecodes,
+    inviteredemptions,
+    messages,
+    notifications,
+    playlists,
+    playlistvideos,
     userfriends,
     usermaster,
     uservideos,
+    videoreactions,
+    videoshares,
+    videosimilarities,
     videos,
+    videoviews,
 );
@@ -0,0 +1,124 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::QueryResult;
+
+use tubepeek_server_rust::models::{TasteOverlap, TopWatchGroup, WatchCountBucket, WatchTimeTotals};
+use tubepeek_server_rust::repositories::StatsRepository;
+
+
+const DAILY_BUCKET_COUNT: i64 = 30;
+const WEEKLY_BUCKET_COUNT: i64 = 12;
+const TOP_GROUP_COUNT: i64 = 5;
+const TASTE_OVERLAP_FRIEND_COUNT: i64 = 5;
+
+pub struct UserStats {
+    pub today: NaiveDate,
+    pub watch_days: Vec<WatchCountBucket>,
+    pub watch_weeks: Vec<WatchCountBucket>,
+    pub totals: WatchTimeTotals,
+    pub top_channels: Vec<TopWatchGroup>,
+    pub top_categories: Vec<TopWatchGroup>,
+    pub taste_overlaps: Vec<TasteOverlap>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WatchStreaks {
+    pub current: i64,
+    pub longest: i64,
+}
+
+impl UserStats {
+    pub fn daily_counts(&self) -> impl Iterator<Item = &WatchCountBucket> {
+        let first_day = self.today - Duration::days(DAILY_BUCKET_COUNT - 1);
+        self.watch_days.iter().filter(move |day| day.bucket >= first_day)
+    }
+
+    pub fn streaks(&self) -> WatchStreaks {
+        let days: Vec<NaiveDate> = self.watch_days.iter().map(|day| day.bucket).collect();
+        watch_streaks(&days, self.today)
+    }
+}
+
+pub fn load_user_stats(google_user_id: &str, utc_offset_minutes: i32, now: NaiveDateTime, stats: &dyn StatsRepository) -> QueryResult<UserStats> {
+    let local_now = now + Duration::minutes(utc_offset_minutes as i64);
+    let today = local_now.date();
+
+    let this_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let first_week_start = (this_week - Duration::weeks(WEEKLY_BUCKET_COUNT - 1)).and_hms_opt(0, 0, 0).unwrap()
+        - Duration::minutes(utc_offset_minutes as i64);
+
+    let watch_days = stats.watch_days(google_user_id, utc_offset_minutes)?;
+    let watch_weeks = stats.watch_weeks(google_user_id, utc_offset_minutes, first_week_start)?;
+    let totals = stats.watch_time_totals(google_user_id)?;
+    let top_channels = stats.top_channels(google_user_id, TOP_GROUP_COUNT)?;
+    let top_categories = stats.top_categories(google_user_id, TOP_GROUP_COUNT)?;
+    let taste_overlaps = stats.taste_overlaps(google_user_id, TASTE_OVERLAP_FRIEND_COUNT)?;
+
+    Ok(UserStats { today, watch_days, watch_weeks, totals, top_channels, top_categories, taste_overlaps })
+}
+
+// `days` ascending and distinct. A streak is still current when the last watch was yesterday,
+// today simply has not been watched yet.
+pub fn watch_streaks(days: &[NaiveDate], today: NaiveDate) -> WatchStreaks {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous_day: Option<NaiveDate> = None;
+
+    for day in days {
+        run = match previous_day {
+            Some(previous_day) if *day - previous_day == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous_day = Some(*day);
+    }
+
+    let current = match previous_day {
+        Some(last_day) if today - last_day <= Duration::days(1) => run,
+        _ => 0,
+    };
+
+    WatchStreaks { current, longest }
+}
+
+// YouTube only hands out category ids, these are the ones its Data API assigns to videos
+pub fn youtube_category_name(category_id: &str) -> Option<&'static str> {
+    let name = match category_id {
+        "1" => "Film & Animation",
+        "2" => "Autos & Vehicles",
+        "10" => "Music",
+        "15" => "Pets & Animals",
+        "17" => "Sports",
+        "19" => "Travel & Events",
+        "20" => "Gaming",
+        "22" => "People & Blogs",
+        "23" => "Comedy",
+        "24" => "Entertainment",
+        "25" => "News & Politics",
+        "26" => "Howto & Style",
+        "27" => "Education",
+        "28" => "Science & Technology",
+        "29" => "Nonprofits & Activism",
+        _ => return None,
+    };
+    Some(name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day_of_month: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day_of_month).unwrap()
+    }
+
+    #[test]
+    fn streaks_count_consecutive_days() {
+        let days = vec![day(1), day(2), day(3), day(7), day(9), day(10)];
+
+        assert_eq!(watch_streaks(&days, day(10)), WatchStreaks { current: 2, longest: 3 });
+        assert_eq!(watch_streaks(&days, day(11)), WatchStreaks { current: 2, longest: 3 });
+        assert_eq!(watch_streaks(&days, day(12)), WatchStreaks { current: 0, longest: 3 });
+        assert_eq!(watch_streaks(&[], day(12)), WatchStreaks { current: 0, longest: 0 });
+    }
+}
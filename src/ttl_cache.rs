@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+
+// Expired entries are dropped lazily, on lookup or once the map grows past `capacity`
+#[derive(Debug)]
+pub struct TtlCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (Instant, V)>,
+}
+
+impl<K: Eq + Hash + Clone, V> TtlCache<K, V> {
+    pub fn new(capacity: usize) -> TtlCache<K, V> {
+        TtlCache { capacity, entries: HashMap::new() }
+    }
+
+    pub fn get(&mut self, key: &K, now: Instant) -> Option<&V> {
+        let is_expired = match self.entries.get(key) {
+            Some((expires_at, _)) => *expires_at <= now,
+            None => return None,
+        };
+        if is_expired {
+            self.entries.remove(key);
+            return None;
+        }
+        self.entries.get(key).map(|(_, value)| value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration, now: Instant) {
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+        // Still full of live entries, make room by dropping the one closest to expiry
+        if self.entries.len() >= self.capacity {
+            let soonest_expiring = self.entries.iter()
+                .min_by_key(|(_, (expires_at, _))| *expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest_expiring) = soonest_expiring {
+                self.entries.remove(&soonest_expiring);
+            }
+        }
+        self.entries.insert(key, (now + ttl, value));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_their_ttl() {
+        let start = Instant::now();
+        let mut cache = TtlCache::new(10);
+        cache.insert("feed", 1, Duration::from_secs(60), start);
+
+        assert_eq!(cache.get(&"feed", start + Duration::from_secs(59)), Some(&1));
+        assert_eq!(cache.get(&"feed", start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn full_cache_evicts_expired_then_soonest_expiring() {
+        let start = Instant::now();
+        let mut cache = TtlCache::new(2);
+        cache.insert("short", 1, Duration::from_secs(10), start);
+        cache.insert("long", 2, Duration::from_secs(100), start);
+        cache.insert("newest", 3, Duration::from_secs(50), start + Duration::from_secs(1));
+
+        assert_eq!(cache.get(&"short", start), None);
+        assert_eq!(cache.get(&"long", start), Some(&2));
+        assert_eq!(cache.get(&"newest", start), Some(&3));
+    }
+}
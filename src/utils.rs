@@ -1,16 +1,243 @@
 use regex::Regex;
-use std::string::ToString;
+use url::Url;
+use chrono::{NaiveDateTime, TimeZone, Utc};
 
 
-// Got regex from the implementation of https://docs.rs/rafy/0.2.1/rafy/
-pub fn get_youtube_videoid(videoUrl: &str) -> Option<String> {
+#[derive(Debug, Clone, PartialEq)]
+pub enum YoutubeUrlKind {
+    Watch,
+    ShortLink,
+    Shorts,
+    Live,
+    Embed,
+    Legacy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct YoutubeUrl {
+    pub video_id: String,
+    pub start_seconds: Option<i64>,
+    pub playlist_id: Option<String>,
+    pub index: Option<u32>,
+    pub kind: YoutubeUrlKind,
+}
+
+const YOUTUBE_HOSTS: [&str; 6] = [
+    "youtube.com", "www.youtube.com", "m.youtube.com", "music.youtube.com",
+    "youtube-nocookie.com", "www.youtube-nocookie.com",
+];
+
+// Users paste urls with or without the scheme, so "youtu.be/xyz" is as good as "https://youtu.be/xyz"
+pub fn parse_url_lenient(video_url: &str) -> Option<Url> {
+    let video_url = video_url.trim();
+    if video_url.contains("://") {
+        Url::parse(video_url).ok()
+    } else {
+        Url::parse(&format!("https://{}", video_url)).ok()
+    }
+}
+
+pub fn parse_youtube_url(video_url: &str) -> Option<YoutubeUrl> {
+    let parsed_url = parse_url_lenient(video_url)?;
+
+    let host = parsed_url.host_str()?.to_lowercase();
+    let path_segments: Vec<&str> = parsed_url.path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default();
+    let query_param = |name: &str| -> Option<String> {
+        parsed_url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let (video_id, kind) = if host == "youtu.be" || host == "www.youtu.be" {
+        (path_segments.first()?.to_string(), YoutubeUrlKind::ShortLink)
+    } else if YOUTUBE_HOSTS.contains(&host.as_str()) {
+        match path_segments.as_slice() {
+            ["watch"] => (query_param("v").or_else(|| query_param("vi"))?, YoutubeUrlKind::Watch),
+            ["attribution_link"] => {
+                // The shared URL is carried, percent-encoded, in the `u` parameter
+                let inner_path = query_param("u")?;
+                return parse_youtube_url(&format!("https://www.youtube.com{}", inner_path));
+            },
+            ["shorts", id, ..] => (id.to_string(), YoutubeUrlKind::Shorts),
+            ["live", id, ..] => (id.to_string(), YoutubeUrlKind::Live),
+            // "videoseries" happens to be 11 characters long but embeds a whole playlist
+            ["embed", "videoseries", ..] => return None,
+            ["embed", id, ..] => (id.to_string(), YoutubeUrlKind::Embed),
+            ["v", id, ..] | ["vi", id, ..] | ["e", id, ..] => (id.to_string(), YoutubeUrlKind::Legacy),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    if !is_valid_youtube_videoid(&video_id) {
+        return None;
+    }
+
+    let fragment_start = parsed_url.fragment()
+        .and_then(|fragment| fragment.strip_prefix("t="))
+        .and_then(parse_timestamp_offset);
+
+    Some(YoutubeUrl {
+        video_id,
+        start_seconds: query_param("t")
+            .or_else(|| query_param("start"))
+            .and_then(|t| parse_timestamp_offset(&t))
+            .or(fragment_start),
+        playlist_id: query_param("list").filter(|list| is_valid_youtube_playlistid(list)),
+        index: query_param("index").and_then(|index| index.parse::<u32>().ok()),
+        kind,
+    })
+}
+
+fn is_valid_youtube_videoid(video_id: &str) -> bool {
+    video_id.len() == 11
+        && video_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn is_valid_youtube_playlistid(playlist_id: &str) -> bool {
+    !playlist_id.is_empty()
+        && playlist_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Accepts the offsets video sites put in `t=` and `start=`: "90", "90s", "1m30s", "1h2m3s"
+pub fn parse_timestamp_offset(timestamp: &str) -> Option<i64> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"^.*(?:(?:youtu\.be/|v/|vi/|u/w/|embed/)|(?:(?:watch)?\?v(?:i)?=|\&v(?:i)?=))([^#\&\?]*).*").unwrap();
+        static ref RE: Regex = Regex::new(r"^(?:(\d+)h)?(?:(\d+)m)?(?:(\d+)s?)?$").unwrap();
     }
 
-    if RE.is_match(videoUrl) {
-        let vid_split = RE.captures(videoUrl).unwrap();
-        return Some(vid_split.get(1).unwrap().as_str().to_string());
+    if timestamp.is_empty() {
+        return None;
+    }
+    let captures = RE.captures(timestamp)?;
+
+    let multipliers: [i64; 3] = [3600, 60, 1];
+    let mut total_seconds: i64 = 0;
+
+    for (index, multiplier) in multipliers.iter().enumerate() {
+        if let Some(value) = captures.get(index + 1) {
+            // Absurdly long offsets are rejected rather than overflowing
+            total_seconds = value.as_str().parse::<i64>().ok()?
+                .checked_mul(*multiplier)
+                .and_then(|seconds| total_seconds.checked_add(seconds))?;
+        }
+    }
+    Some(total_seconds)
+}
+
+// Timestamps go over the wire as milliseconds since the epoch, like timeStampInMilliseconds
+pub fn to_milliseconds(date_time: &NaiveDateTime) -> i64 {
+    Utc.from_utc_datetime(date_time).timestamp_millis()
+}
+
+// Parses the ISO-8601 durations returned in the YouTube contentDetails part, e.g. "PT1H2M10S"
+pub fn parse_iso8601_duration(duration: &str) -> Option<i64> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^P(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?)?$").unwrap();
+    }
+
+    let captures = RE.captures(duration)?;
+    if duration == "P" || duration.ends_with('T') {
+        return None;
+    }
+
+    let multipliers: [i64; 5] = [7 * 24 * 3600, 24 * 3600, 3600, 60, 1];
+    let mut total_seconds: i64 = 0;
+
+    for (index, multiplier) in multipliers.iter().enumerate() {
+        if let Some(value) = captures.get(index + 1) {
+            total_seconds = value.as_str().parse::<i64>().ok()?
+                .checked_mul(*multiplier)
+                .and_then(|seconds| total_seconds.checked_add(seconds))?;
+        }
+    }
+    Some(total_seconds)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type ExpectedYoutubeUrl<'a> = (&'a str, Option<i64>, Option<&'a str>, Option<u32>, YoutubeUrlKind);
+
+    #[test]
+    fn parses_real_world_youtube_urls() {
+        use YoutubeUrlKind::*;
+
+        let cases: Vec<(&str, Option<ExpectedYoutubeUrl>)> = vec![
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("http://youtube.com/watch?v=dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("www.youtube.com/watch?v=dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?feature=share&v=dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?vi=dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42", Some(("dQw4w9WgXcQ", Some(42), None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42s", Some(("dQw4w9WgXcQ", Some(42), None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1h2m3s", Some(("dQw4w9WgXcQ", Some(3723), None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=1m30s", Some(("dQw4w9WgXcQ", Some(90), None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=9223372036854775807h", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=2562047788015215h59m", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI&index=4",
+                Some(("dQw4w9WgXcQ", None, Some("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"), Some(4), Watch))),
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ&feature=youtu.be", Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVMdQw4w9WgXcQ",
+                Some(("dQw4w9WgXcQ", None, Some("RDAMVMdQw4w9WgXcQ"), None, Watch))),
+            ("https://youtu.be/dQw4w9WgXcQ", Some(("dQw4w9WgXcQ", None, None, None, ShortLink))),
+            ("https://youtu.be/dQw4w9WgXcQ?t=10", Some(("dQw4w9WgXcQ", Some(10), None, None, ShortLink))),
+            ("https://youtu.be/dQw4w9WgXcQ?si=B_RZg_I-lLaa7UU-", Some(("dQw4w9WgXcQ", None, None, None, ShortLink))),
+            ("https://www.youtube.com/shorts/aqz-KE-bpKQ", Some(("aqz-KE-bpKQ", None, None, None, Shorts))),
+            ("https://youtube.com/shorts/aqz-KE-bpKQ?feature=share", Some(("aqz-KE-bpKQ", None, None, None, Shorts))),
+            ("https://www.youtube.com/live/jfKfPfyJRdk?si=abc", Some(("jfKfPfyJRdk", None, None, None, Live))),
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ?start=30", Some(("dQw4w9WgXcQ", Some(30), None, None, Embed))),
+            ("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ?rel=0", Some(("dQw4w9WgXcQ", None, None, None, Embed))),
+            ("https://www.youtube.com/v/dQw4w9WgXcQ?version=3", Some(("dQw4w9WgXcQ", None, None, None, Legacy))),
+            ("https://www.youtube.com/attribution_link?a=8g8kPrPIi-ecwIsS&u=/watch%3Fv%3DdQw4w9WgXcQ%26feature%3Dshare",
+                Some(("dQw4w9WgXcQ", None, None, None, Watch))),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ-extra-junk", None),
+            ("https://www.youtube.com/watch?v=short", None),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXc!", None),
+            ("https://www.youtube.com/watch", None),
+            ("https://www.youtube.com/embed/videoseries?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI", None),
+            ("https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw", None),
+            ("https://www.youtube.com/", None),
+            ("https://vimeo.com/76979871", None),
+            ("https://notyoutube.com/watch?v=dQw4w9WgXcQ", None),
+            ("not a url at all", None),
+        ];
+
+        for (video_url, expected) in cases {
+            let expected = expected.map(|(video_id, start_seconds, playlist_id, index, kind)| YoutubeUrl {
+                video_id: video_id.to_string(),
+                start_seconds,
+                playlist_id: playlist_id.map(|p| p.to_string()),
+                index,
+                kind,
+            });
+            assert_eq!(parse_youtube_url(video_url), expected, "parsing {}", video_url);
+        }
+    }
+
+    #[test]
+    fn parses_iso8601_durations() {
+        let cases = vec![
+            ("PT4M13S", Some(253)),
+            ("PT1H2M10S", Some(3730)),
+            ("PT45S", Some(45)),
+            ("P1DT2H", Some(93600)),
+            ("P1W", Some(604800)),
+            ("P0D", Some(0)),
+            ("PT9223372036854775807H", None),
+            ("P15250284452472W", None),
+            ("P15250284452471WT1000000S", None),
+            ("PT", None),
+            ("P", None),
+            ("4M13S", None),
+        ];
+
+        for (duration, expected) in cases {
+            assert_eq!(parse_iso8601_duration(duration), expected, "parsing {}", duration);
+        }
     }
-    None
 }
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use serde::Serialize;
+
+use crate::ws_dto::PlaybackState;
+use crate::WsConnectedClientCurrentVideo;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[allow(non_snake_case)]
+pub struct WatchPartyMember {
+    #[serde(skip_serializing)]
+    pub socketId: u32,
+    pub googleUserId: String,
+}
+
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+pub struct WatchParty {
+    pub partyId: u32,
+    pub host: WatchPartyMember,
+    pub videoData: WsConnectedClientCurrentVideo,
+
+    // In join order, the host included. The next in line becomes host when the host leaves.
+    pub members: Vec<WatchPartyMember>,
+
+    #[serde(skip_serializing)]
+    pub invitedGoogleUserIds: Vec<String>,
+
+    pub playbackState: PlaybackState,
+    pub positionInSeconds: f64,
+    pub serverTimeStampInMilliseconds: i64,
+}
+
+impl WatchParty {
+    pub fn is_member(&self, socket_id: u32) -> bool {
+        self.members.iter().any(|member| member.socketId == socket_id)
+    }
+
+    pub fn is_invited(&self, google_user_id: &str) -> bool {
+        self.invitedGoogleUserIds.iter().any(|invited| invited == google_user_id)
+    }
+
+    pub fn member_sockets(&self) -> Vec<u32> {
+        self.members.iter().map(|member| member.socketId).collect()
+    }
+
+    // Where playback is right now, extrapolated from the last authoritative event
+    pub fn current_position(&self, now_in_milliseconds: i64) -> f64 {
+        match self.playbackState {
+            PlaybackState::Playing => {
+                let elapsed_milliseconds = (now_in_milliseconds - self.serverTimeStampInMilliseconds).max(0);
+                self.positionInSeconds + elapsed_milliseconds as f64 / 1000.0
+            },
+            _ => self.positionInSeconds
+        }
+    }
+
+    pub fn set_playback(&mut self, state: PlaybackState, position_in_seconds: f64, now_in_milliseconds: i64) {
+        self.playbackState = state;
+        self.positionInSeconds = position_in_seconds;
+        self.serverTimeStampInMilliseconds = now_in_milliseconds;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WatchPartyDeparture {
+    NotAMember,
+    Left,
+    HostChanged(WatchPartyMember),
+    Ended,
+}
+
+#[derive(Debug, Default)]
+pub struct WatchParties {
+    next_party_id: u32,
+    parties: HashMap<u32, WatchParty>,
+}
+
+impl WatchParties {
+    pub fn start(&mut self, host: WatchPartyMember, video: WsConnectedClientCurrentVideo, now_in_milliseconds: i64) -> &WatchParty {
+        self.next_party_id += 1;
+        let party_id = self.next_party_id;
+
+        let party = WatchParty {
+            partyId: party_id,
+            host: host.clone(),
+            playbackState: video.playbackState,
+            positionInSeconds: video.positionInSeconds,
+            serverTimeStampInMilliseconds: now_in_milliseconds,
+            videoData: video,
+            members: vec![host],
+            invitedGoogleUserIds: vec![],
+        };
+
+        self.parties.entry(party_id).or_insert(party)
+    }
+
+    pub fn get(&self, party_id: u32) -> Option<&WatchParty> {
+        self.parties.get(&party_id)
+    }
+
+    pub fn get_mut(&mut self, party_id: u32) -> Option<&mut WatchParty> {
+        self.parties.get_mut(&party_id)
+    }
+
+    pub fn party_of(&self, socket_id: u32) -> Option<u32> {
+        self.parties.values()
+            .find(|party| party.is_member(socket_id))
+            .map(|party| party.partyId)
+    }
+
+    pub fn leave(&mut self, party_id: u32, socket_id: u32) -> WatchPartyDeparture {
+        let party = match self.parties.get_mut(&party_id) {
+            Some(party) if party.is_member(socket_id) => party,
+            _ => return WatchPartyDeparture::NotAMember
+        };
+
+        party.members.retain(|member| member.socketId != socket_id);
+
+        if party.members.is_empty() {
+            self.parties.remove(&party_id);
+            return WatchPartyDeparture::Ended;
+        }
+        if party.host.socketId == socket_id {
+            party.host = party.members[0].clone();
+            return WatchPartyDeparture::HostChanged(party.host.clone());
+        }
+        WatchPartyDeparture::Left
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::VideoThumbnails;
+
+    fn member(socket_id: u32, google_user_id: &str) -> WatchPartyMember {
+        WatchPartyMember { socketId: socket_id, googleUserId: google_user_id.to_string() }
+    }
+
+    fn video() -> WsConnectedClientCurrentVideo {
+        WsConnectedClientCurrentVideo {
+            platform: "youtube".to_string(),
+            externalId: "dQw4w9WgXcQ".to_string(),
+            videoUrl: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            startSeconds: None,
+            playlistId: None,
+            playlistIndex: None,
+            title: "Never Gonna Give You Up".to_string(),
+            thumbnail_url: "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg".to_string(),
+            thumbnails: VideoThumbnails {
+                default: "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg".to_string(),
+                medium: None,
+                high: None,
+                maxres: None,
+            },
+            channelId: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            channelTitle: "Rick Astley".to_string(),
+            publishedAt: None,
+            categoryId: None,
+            durationInSeconds: Some(213),
+            isLiveBroadcast: false,
+            timeStampInMilliseconds: 0,
+            playbackState: PlaybackState::Playing,
+            positionInSeconds: 10.0,
+            positionTimeStampInMilliseconds: 0,
+            playlist: None,
+        }
+    }
+
+    #[test]
+    fn host_hand_off_follows_join_order() {
+        let mut watch_parties = WatchParties::default();
+        let party_id = watch_parties.start(member(1, "host"), video(), 0).partyId;
+
+        let party = watch_parties.get_mut(party_id).unwrap();
+        party.members.push(member(2, "first"));
+        party.members.push(member(3, "second"));
+
+        assert_eq!(watch_parties.leave(party_id, 2), WatchPartyDeparture::Left);
+        assert_eq!(watch_parties.leave(party_id, 1), WatchPartyDeparture::HostChanged(member(3, "second")));
+        assert_eq!(watch_parties.leave(party_id, 1), WatchPartyDeparture::NotAMember);
+        assert_eq!(watch_parties.leave(party_id, 3), WatchPartyDeparture::Ended);
+        assert!(watch_parties.get(party_id).is_none());
+    }
+
+    #[test]
+    fn position_advances_only_while_playing() {
+        let mut watch_parties = WatchParties::default();
+        let party_id = watch_parties.start(member(1, "host"), video(), 1_000).partyId;
+        let party = watch_parties.get_mut(party_id).unwrap();
+
+        assert_eq!(party.current_position(3_500), 12.5);
+
+        party.set_playback(PlaybackState::Paused, 42.0, 5_000);
+        assert_eq!(party.current_position(9_000), 42.0);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 
@@ -5,7 +7,8 @@ use serde::{Deserialize, Serialize};
 pub struct TakeUserMessage {
     pub action: String,
     pub provider: String,
-    pub authData: AuthData
+    pub authData: AuthData,
+    pub inviteCode: Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,18 +28,217 @@ pub struct OnlineStatusChange {
 #[derive(Serialize, Deserialize)]
 pub struct VideoChangeMessage {
     pub action: String,
-    pub googleUserId: String,
     pub videoUrl: String
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Buffering,
+    Ended
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlaybackStateChangedMessage {
+    pub action: String,
+    pub state: PlaybackState,
+    pub positionInSeconds: f64
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeftVideoMessage {
+    pub action: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JoinFriendVideoMessage {
+    pub action: String,
+    pub friendGoogleUserId: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StartWatchPartyMessage {
+    pub action: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InviteToWatchPartyMessage {
+    pub action: String,
+    pub partyId: u32,
+    pub friendGoogleUserIds: Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WatchPartyMembershipMessage {
+    pub action: String,
+    pub partyId: u32
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WatchPartyPlaybackMessage {
+    pub action: String,
+    pub partyId: u32,
+    pub state: PlaybackState,
+    pub positionInSeconds: f64
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FriendExclusionMessage {
     pub action: String,
-    pub googleUserId: String,
     pub friendGoogleUserId: String,
     pub exclude: bool
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct FriendBlockMessage {
+    pub action: String,
+    pub friendGoogleUserId: String,
+    pub block: bool
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendChatMessage {
+    pub action: String,
+    pub friendGoogleUserId: String,
+    pub body: String,
+    pub clientMessageId: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MarkChatMessagesReadMessage {
+    pub action: String,
+    pub friendGoogleUserId: String,
+    pub upToMessageId: i64
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReactToFriendVideoMessage {
+    pub action: String,
+    pub friendGoogleUserId: String,
+    pub emoji: Option<String>,
+    pub comment: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetWatchHistoryMessage {
+    pub action: String,
+    // Whose history to show, the caller's own when absent
+    pub friendGoogleUserId: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MarkNotificationsReadMessage {
+    pub action: String,
+    pub notificationIds: Option<Vec<i64>>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShareVideoMessage {
+    pub action: String,
+    pub videoUrl: String,
+    pub friendGoogleUserIds: Vec<String>,
+    pub note: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetFriendSuggestionsMessage {
+    pub action: String,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchUsersMessage {
+    pub action: String,
+    pub query: String,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetProfileMessage {
+    pub action: String,
+    pub profileGoogleUserId: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DiscoverabilityMessage {
+    pub action: String,
+    pub isDiscoverable: bool
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateInviteCodeMessage {
+    pub action: String,
+    #[serde(default)]
+    pub multiUse: bool,
+    pub maxUses: Option<i32>,
+    pub expiresInDays: Option<i64>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum TrendingWindow {
+    #[serde(rename = "24h")]
+    Day,
+    #[default]
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl TrendingWindow {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            TrendingWindow::Day => chrono::Duration::hours(24),
+            TrendingWindow::Week => chrono::Duration::days(7),
+            TrendingWindow::Month => chrono::Duration::days(30),
+        }
+    }
+
+    // Wider windows move slower, so they can be served stale for longer
+    pub fn cache_ttl(&self) -> Duration {
+        match self {
+            TrendingWindow::Day => Duration::from_secs(60),
+            TrendingWindow::Week => Duration::from_secs(5 * 60),
+            TrendingWindow::Month => Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetFriendsTrendingMessage {
+    pub action: String,
+    #[serde(default)]
+    pub window: TrendingWindow,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetRecommendationsMessage {
+    pub action: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetMyStatsMessage {
+    pub action: String,
+    // Minutes east of UTC, used to cut the daily and weekly buckets at the user's midnight
+    pub utcOffsetMinutes: Option<i32>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetLeaderboardMessage {
+    pub action: String,
+    // Absent for the all time leaderboard
+    pub window: Option<TrendingWindow>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MakeFriendshipMessage {
     pub action: String,
@@ -53,20 +255,119 @@ pub struct YoutubeVideoResponseItemSnippetThumbnailDetail {
 #[derive(Debug, Deserialize)]
 pub struct YoutubeVideoResponseItemSnippetThumbnail {
     pub default: YoutubeVideoResponseItemSnippetThumbnailDetail,
+    pub medium: Option<YoutubeVideoResponseItemSnippetThumbnailDetail>,
+    pub high: Option<YoutubeVideoResponseItemSnippetThumbnailDetail>,
+    pub maxres: Option<YoutubeVideoResponseItemSnippetThumbnailDetail>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct YoutubeVideoResponseItemSnippet {
     pub title: String,
+    pub channelId: Option<String>,
+    pub channelTitle: Option<String>,
+    pub publishedAt: Option<String>,
+    pub categoryId: Option<String>,
+    pub liveBroadcastContent: Option<String>,
     pub thumbnails: YoutubeVideoResponseItemSnippetThumbnail
 }
 
+#[derive(Debug, Deserialize)]
+pub struct YoutubeVideoResponseItemContentDetails {
+    pub duration: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct YoutubeVideoResponseItem {
     pub snippet: YoutubeVideoResponseItemSnippet,
+    pub contentDetails: Option<YoutubeVideoResponseItemContentDetails>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct YoutubeVideoResponse {
     pub items: Vec<YoutubeVideoResponseItem>
 }
+
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistResponseItemSnippet {
+    pub title: String,
+    pub channelId: String,
+    pub channelTitle: String,
+    pub thumbnails: YoutubeVideoResponseItemSnippetThumbnail
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistResponseItemContentDetails {
+    pub itemCount: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistResponseItem {
+    pub snippet: YoutubePlaylistResponseItemSnippet,
+    pub contentDetails: Option<YoutubePlaylistResponseItemContentDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistResponse {
+    pub items: Vec<YoutubePlaylistResponseItem>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistItemResponseItemSnippet {
+    // Zero based
+    pub position: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistItemResponseItem {
+    pub snippet: YoutubePlaylistItemResponseItemSnippet,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct YoutubePlaylistItemResponse {
+    pub items: Vec<YoutubePlaylistItemResponseItem>
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct VimeoOembedResponse {
+    pub title: String,
+    pub author_name: String,
+    pub author_url: String,
+    pub thumbnail_url: String,
+    pub duration: Option<i64>,
+    pub upload_date: Option<String>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct DailymotionVideoResponse {
+    pub title: String,
+    pub owner: String,
+    #[serde(rename = "owner.screenname")]
+    pub owner_screenname: String,
+    pub created_time: Option<i64>,
+    pub channel: Option<String>,
+    pub duration: Option<i64>,
+    pub onair: Option<bool>,
+    pub thumbnail_120_url: String,
+    pub thumbnail_360_url: Option<String>,
+    pub thumbnail_480_url: Option<String>,
+    pub thumbnail_720_url: Option<String>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct TwitchVideoResponseItem {
+    pub user_id: String,
+    pub user_name: String,
+    pub title: String,
+    pub published_at: String,
+    pub thumbnail_url: String,
+    pub duration: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwitchVideoResponse {
+    pub data: Vec<TwitchVideoResponseItem>
+}